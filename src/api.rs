@@ -0,0 +1,624 @@
+use std::{env, io::{self, Cursor, Read, Write}, net::SocketAddr, sync::{mpsc::Sender, Arc, Mutex}, thread::{self, JoinHandle}, time::{Duration, Instant}};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::json;
+use tiny_http::{Header, Method, Request as HttpRequest, Response, Server};
+use tracing::{error, info, warn};
+
+use crate::{config::Problems, input::{self, InputCode, Reply, Request}, metrics::Metrics, output::OutputHistory};
+
+/// how long the control loop gets to handle a command before the request fails
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// the biggest request body read, far more than any command needs
+const MAX_BODY: u64 = 64 * 1024;
+/// console lines sent when `lines` isn't given
+const DEFAULT_CONSOLE_LINES: usize = 100;
+/// how often a console stream checks for new lines
+const STREAM_POLL: Duration = Duration::from_millis(250);
+/// an idle console stream sends a comment this often so a closed connection is noticed
+const STREAM_KEEPALIVE: Duration = Duration::from_secs(15);
+/// the dashboard page, which gets everything it shows from the API with the token typed into it
+const DASHBOARD: &str = include_str!("dashboard.html");
+/// how often requests with a missing or wrong token are logged, so a client without it can't flood the log
+const REJECTED_LOG_INTERVAL: Duration = Duration::from_secs(60);
+
+/// An HTTP API for managing the server from a web panel or script
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct ApiConfig {
+    /// the address to listen on like `127.0.0.1:8080`, `null` to turn the API off
+    pub bind: Option<String>,
+    /// the environment variable holding the token every request has to send
+    pub token_env: String,
+    /// serve the dashboard page on `/`
+    pub dashboard: bool,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self { bind: None, token_env: "BOUNCER_API_TOKEN".to_string(), dashboard: true }
+    }
+}
+
+impl ApiConfig {
+    pub fn validate(&self, field: &str, problems: &mut Problems) {
+        if let Some(bind) = &self.bind {
+            if bind.parse::<SocketAddr>().is_err() {
+                problems.add(format!("{}.bind", field), format!("{:?} isn't an address and port like \"127.0.0.1:8080\"", bind));
+            }
+        }
+        if self.token_env.is_empty() {
+            problems.add(format!("{}.token_env", field), "can't be empty");
+        }
+    }
+}
+
+/// The HTTP API, handing every action to the control loop the same way the console does. It stops when this is dropped
+pub struct Api {
+    server: Arc<Server>,
+    /// tells the console streams to end
+    stopped: Arc<Mutex<bool>>,
+    listen_thread: Option<JoinHandle<()>>,
+}
+
+/// What every request is handled with
+struct Shared {
+    token: String,
+    dashboard: bool,
+    requests: Sender<Request>,
+    output: Arc<Mutex<OutputHistory>>,
+    metrics: Arc<Mutex<Metrics>>,
+    stopped: Arc<Mutex<bool>>,
+    rejected: Mutex<Rejected>,
+}
+
+/// Counts the requests turned away for their token between warnings
+#[derive(Default)]
+struct Rejected {
+    count: u64,
+    last_logged: Option<Instant>,
+}
+
+impl Rejected {
+    /// counts another rejected request, giving how many there were once it's time to log them
+    fn add(&mut self, now: Instant) -> Option<u64> {
+        self.count += 1;
+        if self.last_logged.is_some_and(|last_logged| now.duration_since(last_logged) < REJECTED_LOG_INTERVAL) {
+            return None;
+        }
+        self.last_logged = Some(now);
+        Some(std::mem::take(&mut self.count))
+    }
+}
+
+impl Api {
+    /// `None` if the API is turned off or couldn't be started, which is logged
+    pub fn start(config: &ApiConfig, requests: Sender<Request>, output: Arc<Mutex<OutputHistory>>, metrics: Arc<Mutex<Metrics>>) -> Option<Self> {
+        let bind = config.bind.as_ref()?;
+        // the API is never started without a token
+        let token = match env::var(&config.token_env) {
+            Ok(token) if !token.is_empty() => token,
+            _ => {
+                error!("Not starting the API because the environment variable {} with its token isn't set", config.token_env);
+                return None;
+            }
+        };
+        let server = match Server::http(bind) {
+            Ok(server) => Arc::new(server),
+            Err(err) => {
+                error!("Failed to start the API on {}: {}", bind, err);
+                return None;
+            }
+        };
+        let stopped = Arc::new(Mutex::new(false));
+        let shared = Arc::new(Shared {
+            token,
+            dashboard: config.dashboard,
+            requests,
+            output,
+            metrics,
+            stopped: Arc::clone(&stopped),
+            rejected: Mutex::default(),
+        });
+        let server_clone = Arc::clone(&server);
+        let listen_thread = thread::Builder::new()
+            .name("api".to_string())
+            .spawn(move || {
+                for request in server_clone.incoming_requests() {
+                    let shared = Arc::clone(&shared);
+                    // a command waiting on the control loop or a console stream only holds up its own request
+                    thread::spawn(move || handle(request, &shared));
+                }
+            })
+            .unwrap();
+        info!("API listening on http://{}", bind);
+        if config.dashboard {
+            info!("Dashboard at http://{}/", bind);
+        }
+        Some(Self { server, stopped, listen_thread: Some(listen_thread) })
+    }
+}
+
+impl Drop for Api {
+    fn drop(&mut self) {
+        *self.stopped.lock().unwrap() = true;
+        self.server.unblock();
+        if let Some(listen_thread) = self.listen_thread.take() {
+            let _ = listen_thread.join();
+        }
+    }
+}
+
+type JsonResponse = Response<Cursor<Vec<u8>>>;
+
+/// the body of a restart or stop
+#[derive(Deserialize)]
+struct CountdownBody {
+    /// seconds until the server goes down, 30 if left out or 5 with a message
+    delay: Option<u64>,
+    message: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PostponeBody {
+    seconds: u64,
+}
+
+#[derive(Deserialize)]
+struct SayBody {
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct CommandBody {
+    command: String,
+}
+
+/// What to do with a request
+enum Handled {
+    Respond(JsonResponse),
+    /// follow the console from `since`, starting with up to `limit` lines
+    Stream { since: u64, limit: usize },
+}
+
+fn handle(mut request: HttpRequest, shared: &Shared) {
+    let response = match handled(&mut request, shared) {
+        Handled::Respond(response) => response,
+        Handled::Stream { since, limit } => return stream_console(request, since, limit, shared),
+    };
+    if let Err(io_err) = request.respond(response) {
+        error!("Error Answering An API Request {}", io_err);
+    }
+}
+
+fn handled(request: &mut HttpRequest, shared: &Shared) -> Handled {
+    let path = request.url().split('?').next().unwrap_or_default().to_string();
+    // the page has no data in it, so it's the one thing served without the token
+    if shared.dashboard && *request.method() == Method::Get && path == "/" {
+        let content_type = Header::from_bytes("Content-Type", "text/html; charset=utf-8").expect("the header is valid");
+        return Handled::Respond(Response::from_string(DASHBOARD).with_header(content_type));
+    }
+    Handled::Respond(match authorized(request, &shared.token) {
+        true if *request.method() == Method::Get && path == "/api/console/stream" => {
+            let query = request.url().split_once('?').map(|(_, query)| query.to_string()).unwrap_or_default();
+            match console_query(&query) {
+                Ok((since, limit)) => return Handled::Stream { since, limit },
+                Err(response) => response,
+            }
+        },
+        true => route(request, shared),
+        false => {
+            if let Some(count) = shared.rejected.lock().unwrap().add(Instant::now()) {
+                let from = request.remote_addr().map(ToString::to_string).unwrap_or_else(|| "an unknown address".to_string());
+                match count {
+                    1 => warn!("Rejected an API request from {} with a missing or wrong token", from),
+                    _ => warn!("Rejected {} API requests with a missing or wrong token since the last warning, the latest from {}", count, from),
+                }
+            }
+            failed(401, "missing or wrong token, send it as `Authorization: Bearer <token>`")
+        }
+    })
+}
+
+fn authorized(request: &HttpRequest, token: &str) -> bool {
+    let sent = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Authorization"))
+        .and_then(|header| header.value.as_str().strip_prefix("Bearer "));
+    sent.is_some_and(|sent| same_token(sent, token))
+}
+
+/// compares every byte so how long it takes doesn't give the token away
+fn same_token(sent: &str, token: &str) -> bool {
+    sent.len() == token.len() && sent.bytes().zip(token.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn route(request: &mut HttpRequest, shared: &Shared) -> JsonResponse {
+    let requests = &shared.requests;
+    let url = request.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    let code = match (request.method(), path) {
+        (Method::Get, "/api/status") => return answer_json(input::ask(requests, InputCode::Status { json: true }, TIMEOUT)),
+        (Method::Get, "/api/backups") => return answer_json(input::ask(requests, InputCode::RestoreList { json: true }, TIMEOUT)),
+        (Method::Get, "/api/console") => return console(query, &shared.output),
+        (Method::Get, "/metrics") => {
+            // prometheus reads its own text format rather than JSON
+            let content_type = Header::from_bytes("Content-Type", "text/plain; version=0.0.4; charset=utf-8").expect("the header is valid");
+            return Response::from_string(shared.metrics.lock().unwrap().render()).with_header(content_type);
+        },
+        (Method::Post, "/api/restart") => match body::<CountdownBody>(request) {
+            Ok(CountdownBody { delay: Some(0), .. }) => return failed(400, "delay must be more than 0 seconds"),
+            Ok(CountdownBody { message: Some(message), .. }) if message.trim().is_empty() => return failed(400, "message can't be empty"),
+            Ok(CountdownBody { delay: None, message: None }) => InputCode::Restart,
+            Ok(CountdownBody { delay: Some(delay), message: None }) => InputCode::RestartWithTime(delay),
+            Ok(CountdownBody { delay: None, message: Some(message) }) => InputCode::RestartWithMsg(message),
+            Ok(CountdownBody { delay: Some(delay), message: Some(message) }) => InputCode::RestartWithMsgTime(message, delay),
+            Err(response) => return response,
+        },
+        (Method::Post, "/api/stop") => match body::<CountdownBody>(request) {
+            Ok(CountdownBody { delay: Some(0), .. }) => return failed(400, "delay must be more than 0 seconds"),
+            Ok(CountdownBody { message: Some(message), .. }) if message.trim().is_empty() => return failed(400, "message can't be empty"),
+            Ok(CountdownBody { delay: None, message: None }) => InputCode::Exit,
+            Ok(CountdownBody { delay: Some(delay), message: None }) => InputCode::ExitWithTime(delay),
+            Ok(CountdownBody { delay: None, message: Some(message) }) => InputCode::ExitWithMsg(message),
+            Ok(CountdownBody { delay: Some(delay), message: Some(message) }) => InputCode::ExitWithMsgTime(message, delay),
+            Err(response) => return response,
+        },
+        (Method::Post, "/api/start") => InputCode::Start,
+        (Method::Post, "/api/cancel") => InputCode::Cancel,
+        (Method::Post, "/api/postpone") => match body::<PostponeBody>(request) {
+            Ok(PostponeBody { seconds: 0 }) => return failed(400, "seconds must be more than 0"),
+            Ok(PostponeBody { seconds }) => InputCode::Postpone(seconds),
+            Err(response) => return response,
+        },
+        (Method::Post, "/api/backup") => InputCode::Backup,
+        (Method::Post, "/api/say") => match body::<SayBody>(request) {
+            Ok(SayBody { message }) if message.trim().is_empty() => return failed(400, "message can't be empty"),
+            Ok(SayBody { message }) => InputCode::SendMsg(message),
+            Err(response) => return response,
+        },
+        (Method::Post, "/api/command") => match body::<CommandBody>(request) {
+            Ok(CommandBody { command }) if command.trim().is_empty() => return failed(400, "command can't be empty"),
+            Ok(CommandBody { command }) => InputCode::Cmd(command),
+            Err(response) => return response,
+        },
+        (_, "/metrics" | "/api/status" | "/api/backups" | "/api/console" | "/api/console/stream" | "/api/restart" | "/api/stop" | "/api/start" | "/api/cancel" | "/api/postpone" | "/api/backup" | "/api/say" | "/api/command") => {
+            return failed(405, "method not allowed")
+        },
+        _ => return failed(404, "not found"),
+    };
+    answer(input::ask(requests, code, TIMEOUT))
+}
+
+/// Reads a JSON body. An empty body is read as `{}` so actions with only optional fields can be sent without one
+fn body<T: DeserializeOwned>(request: &mut HttpRequest) -> Result<T, JsonResponse> {
+    let mut body = String::new();
+    if request.as_reader().take(MAX_BODY).read_to_string(&mut body).is_err() {
+        return Err(failed(400, "the body isn't UTF-8"));
+    }
+    if body.trim().is_empty() {
+        body = "{}".to_string();
+    }
+    serde_json::from_str(&body).map_err(|err| failed(400, &format!("invalid body: {}", err)))
+}
+
+/// Reads `since` and `lines` from the query of a console request
+fn console_query(query: &str) -> Result<(u64, usize), JsonResponse> {
+    let param = |name: &str| query.split('&').find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='));
+    let Ok(since) = param("since").map(str::parse::<u64>).transpose() else {
+        return Err(failed(400, "since must be a number"));
+    };
+    let Ok(limit) = param("lines").map(str::parse::<usize>).transpose() else {
+        return Err(failed(400, "lines must be a number"));
+    };
+    Ok((since.unwrap_or(0), limit.unwrap_or(DEFAULT_CONSOLE_LINES)))
+}
+
+/// the recent console output, or just the lines after `since` to follow it
+fn console(query: &str, output: &Mutex<OutputHistory>) -> JsonResponse {
+    let (since, limit) = match console_query(query) {
+        Ok(query) => query,
+        Err(response) => return response,
+    };
+    let (lines, next) = output.lock().unwrap().since(since);
+    let lines = &lines[lines.len().saturating_sub(limit)..];
+    respond(200, json!({ "lines": lines, "next": next }).to_string())
+}
+
+/// Sends the console as server-sent events, starting with the same lines as `/api/console` and then every line as it's printed,
+/// until the client goes away or the API stops. Every event holds a batch of lines, one per `data:`, with the `next` to resume from as its id.
+/// The response is written by hand because tiny_http holds back a chunked body until it has 8KiB of it
+fn stream_console(request: HttpRequest, since: u64, limit: usize, shared: &Shared) {
+    let mut writer = request.into_writer();
+    let head = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nTransfer-Encoding: chunked\r\n\r\n";
+    if writer.write_all(head.as_bytes()).and_then(|_| writer.flush()).is_err() {
+        return;
+    }
+    let mut seen = since;
+    let mut first = true;
+    let mut last_sent = Instant::now();
+    let result = loop {
+        if *shared.stopped.lock().unwrap() {
+            break Ok(());
+        }
+        let (lines, next) = shared.output.lock().unwrap().since(seen);
+        let lines = if first { &lines[lines.len().saturating_sub(limit)..] } else { &lines[..] };
+        let event = if !lines.is_empty() {
+            let data = lines.iter().flat_map(|line| line.lines()).map(|line| format!("data: {}\n", line)).collect::<String>();
+            Some(format!("id: {}\n{}\n", next, data))
+        } else if last_sent.elapsed() >= STREAM_KEEPALIVE {
+            Some(": keepalive\n\n".to_string())
+        } else {
+            None
+        };
+        seen = next;
+        first = false;
+        match event {
+            Some(event) => {
+                if let Err(io_err) = write_chunk(&mut writer, event.as_bytes()) {
+                    break Err(io_err);
+                }
+                last_sent = Instant::now();
+            },
+            None => thread::sleep(STREAM_POLL),
+        }
+    };
+    // the client going away is how a stream usually ends, so only a clean end gets the last chunk
+    if result.is_ok() {
+        let _ = write_chunk(&mut writer, b"");
+    }
+}
+
+/// Writes one chunk of a chunked body, an empty one ends it
+fn write_chunk(writer: &mut impl Write, data: &[u8]) -> io::Result<()> {
+    write!(writer, "{:x}\r\n", data.len())?;
+    writer.write_all(data)?;
+    writer.write_all(b"\r\n")?;
+    writer.flush()
+}
+
+fn answer(reply: Reply) -> JsonResponse {
+    match reply {
+        Reply::Done(text) => respond(200, json!({ "ok": true, "reply": text }).to_string()),
+        Reply::Failed(text) => failed(409, &text),
+    }
+}
+
+/// for commands like `status --json` whose reply is already JSON
+fn answer_json(reply: Reply) -> JsonResponse {
+    match reply {
+        Reply::Done(json) => respond(200, json),
+        Reply::Failed(text) => failed(409, &text),
+    }
+}
+
+fn failed(status: u16, error: &str) -> JsonResponse {
+    respond(status, json!({ "ok": false, "error": error }).to_string())
+}
+
+fn respond(status: u16, body: String) -> JsonResponse {
+    let content_type = Header::from_bytes("Content-Type", "application/json").expect("the header is valid");
+    Response::from_string(body).with_status_code(status).with_header(content_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use serde_json::Value;
+    use tiny_http::TestRequest;
+
+    use super::*;
+
+    const TOKEN: &str = "s3cret-token";
+
+    /// Handles requests like the API does, with a stand-in for the control loop that remembers every command it gets.
+    /// `backup` is refused like it is while one is running
+    fn shared(dashboard: bool) -> (Shared, Arc<Mutex<Vec<InputCode>>>) {
+        let (requests, requests_rx) = mpsc::channel::<Request>();
+        let codes = Arc::new(Mutex::new(Vec::new()));
+        let codes_clone = Arc::clone(&codes);
+        thread::spawn(move || {
+            for request in requests_rx {
+                let reply = match &request.code {
+                    InputCode::Status { json: true } => Reply::Done(r#"{"state":"Normal"}"#.to_string()),
+                    InputCode::Backup => Reply::Failed("Error: A Backup Is Already Running".to_string()),
+                    _ => Reply::Done("done".to_string()),
+                };
+                codes_clone.lock().unwrap().push(request.code.clone());
+                request.answer(reply);
+            }
+        });
+        let mut output = OutputHistory::new(10);
+        for i in 0..5 {
+            output.push(format!("line {}", i));
+        }
+        let shared = Shared {
+            token: TOKEN.to_string(),
+            dashboard,
+            requests,
+            output: Arc::new(Mutex::new(output)),
+            metrics: Arc::new(Mutex::new(Metrics::default())),
+            stopped: Arc::new(Mutex::new(false)),
+            rejected: Mutex::default(),
+        };
+        (shared, codes)
+    }
+
+    fn request(method: Method, path: &str, token: Option<&str>, body: &'static str) -> HttpRequest {
+        let mut request = TestRequest::new().with_method(method).with_path(path).with_body(body);
+        if let Some(token) = token {
+            request = request.with_header(Header::from_bytes("Authorization", token).unwrap());
+        }
+        request.into()
+    }
+
+    /// the status and body of the response to a request sent with the right token
+    fn call(shared: &Shared, method: Method, path: &str, body: &'static str) -> (u16, String) {
+        send(shared, request(method, path, Some(&format!("Bearer {}", TOKEN)), body))
+    }
+
+    fn send(shared: &Shared, mut request: HttpRequest) -> (u16, String) {
+        match handled(&mut request, shared) {
+            Handled::Respond(response) => {
+                let status = response.status_code().0;
+                (status, String::from_utf8(response.into_reader().into_inner()).unwrap())
+            },
+            Handled::Stream { .. } => panic!("expected a response, got a stream"),
+        }
+    }
+
+    fn json(body: &str) -> Value {
+        serde_json::from_str(body).unwrap()
+    }
+
+    #[test]
+    fn requests_without_the_token_are_turned_away() {
+        let (shared, codes) = shared(true);
+        for token in [None, Some("Bearer wrong-token!"), Some("Bearer s3cret-toke"), Some("Bearer s3cret-token2"), Some("bearer s3cret-token"), Some("s3cret-token")] {
+            let (status, body) = send(&shared, request(Method::Post, "/api/stop", token, ""));
+            assert_eq!(status, 401, "{:?}", token);
+            assert_eq!(json(&body)["ok"], false);
+        }
+        assert_eq!(send(&shared, request(Method::Get, "/metrics", None, "")).0, 401);
+        assert!(codes.lock().unwrap().is_empty());
+
+        // the dashboard is only a page, what it shows needs the token
+        let (status, page) = send(&shared, request(Method::Get, "/", None, ""));
+        assert_eq!(status, 200);
+        assert!(page.contains("<html"));
+        let (shared, _) = self::shared(false);
+        assert_eq!(send(&shared, request(Method::Get, "/", None, "")).0, 401);
+        assert_eq!(call(&shared, Method::Get, "/", "").0, 404);
+    }
+
+    #[test]
+    fn tokens_are_compared_whole() {
+        assert!(same_token(TOKEN, TOKEN));
+        assert!(!same_token("s3cret-tokeN", TOKEN));
+        assert!(!same_token("s3cret", TOKEN));
+        assert!(!same_token("s3cret-token-and-more", TOKEN));
+        assert!(!same_token("", TOKEN));
+    }
+
+    #[test]
+    fn rejections_are_logged_once_a_minute() {
+        let mut rejected = Rejected::default();
+        let start = Instant::now();
+        assert_eq!(rejected.add(start), Some(1));
+        assert_eq!(rejected.add(start + Duration::from_secs(1)), None);
+        assert_eq!(rejected.add(start + Duration::from_secs(59)), None);
+        assert_eq!(rejected.add(start + Duration::from_secs(60)), Some(3));
+        assert_eq!(rejected.add(start + Duration::from_secs(61)), None);
+    }
+
+    #[test]
+    fn actions_become_commands() {
+        let (shared, codes) = shared(true);
+        let actions = [
+            ("/api/restart", ""),
+            ("/api/restart", r#"{"delay": 600}"#),
+            ("/api/restart", r#"{"message": "Updating mods"}"#),
+            ("/api/restart", r#"{"delay": 600, "message": "Updating mods"}"#),
+            ("/api/stop", "{}"),
+            ("/api/stop", r#"{"delay": 60}"#),
+            ("/api/stop", r#"{"message": "Bye"}"#),
+            ("/api/stop", r#"{"delay": 60, "message": "Bye"}"#),
+            ("/api/start", ""),
+            ("/api/cancel", ""),
+            ("/api/postpone", r#"{"seconds": 90}"#),
+            ("/api/say", r#"{"message": "Hello"}"#),
+            ("/api/command", r#"{"command": "op Steve"}"#),
+        ];
+        for (path, body) in actions {
+            let (status, reply) = call(&shared, Method::Post, path, body);
+            assert_eq!((status, json(&reply)), (200, json!({ "ok": true, "reply": "done" })), "{} {}", path, body);
+        }
+        assert_eq!(
+            *codes.lock().unwrap(),
+            [
+                InputCode::Restart,
+                InputCode::RestartWithTime(600),
+                InputCode::RestartWithMsg("Updating mods".to_string()),
+                InputCode::RestartWithMsgTime("Updating mods".to_string(), 600),
+                InputCode::Exit,
+                InputCode::ExitWithTime(60),
+                InputCode::ExitWithMsg("Bye".to_string()),
+                InputCode::ExitWithMsgTime("Bye".to_string(), 60),
+                InputCode::Start,
+                InputCode::Cancel,
+                InputCode::Postpone(90),
+                InputCode::SendMsg("Hello".to_string()),
+                InputCode::Cmd("op Steve".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn refused_commands_are_conflicts() {
+        let (shared, _) = shared(true);
+        let (status, body) = call(&shared, Method::Post, "/api/backup", "");
+        assert_eq!((status, json(&body)), (409, json!({ "ok": false, "error": "Error: A Backup Is Already Running" })));
+    }
+
+    #[test]
+    fn bad_bodies_are_bad_requests() {
+        let (shared, codes) = shared(true);
+        for (path, body) in [
+            ("/api/restart", r#"{"delay": 0}"#),
+            ("/api/restart", r#"{"message": "  "}"#),
+            ("/api/restart", r#"{"delay": "soon"}"#),
+            ("/api/stop", r#"{"delay": 0, "message": "Bye"}"#),
+            ("/api/stop", "not json"),
+            ("/api/postpone", ""),
+            ("/api/postpone", r#"{"seconds": 0}"#),
+            ("/api/say", r#"{"message": ""}"#),
+            ("/api/command", r#"{"cmd": "op Steve"}"#),
+        ] {
+            let (status, reply) = call(&shared, Method::Post, path, body);
+            assert_eq!(status, 400, "{} {}", path, body);
+            assert_eq!(json(&reply)["ok"], false);
+        }
+        assert!(codes.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn unknown_paths_and_methods() {
+        let (shared, _) = shared(true);
+        assert_eq!(call(&shared, Method::Get, "/api/restart", "").0, 405);
+        assert_eq!(call(&shared, Method::Post, "/api/status", "").0, 405);
+        assert_eq!(call(&shared, Method::Delete, "/api/console/stream", "").0, 405);
+        assert_eq!(call(&shared, Method::Get, "/api/nothing", "").0, 404);
+    }
+
+    #[test]
+    fn status_and_metrics() {
+        let (shared, _) = shared(true);
+        assert_eq!(call(&shared, Method::Get, "/api/status", ""), (200, r#"{"state":"Normal"}"#.to_string()));
+        let (status, metrics) = call(&shared, Method::Get, "/metrics", "");
+        assert_eq!(status, 200);
+        assert!(metrics.contains("# TYPE minecraft_server_up gauge\nminecraft_server_up 0\n"));
+    }
+
+    #[test]
+    fn console_lines_and_since() {
+        let (shared, _) = shared(true);
+        let console = |path| json(&call(&shared, Method::Get, path, "").1);
+        assert_eq!(console("/api/console"), json!({ "lines": ["line 0", "line 1", "line 2", "line 3", "line 4"], "next": 5 }));
+        assert_eq!(console("/api/console?lines=2"), json!({ "lines": ["line 3", "line 4"], "next": 5 }));
+        assert_eq!(console("/api/console?since=3"), json!({ "lines": ["line 3", "line 4"], "next": 5 }));
+        assert_eq!(console("/api/console?since=1&lines=1"), json!({ "lines": ["line 4"], "next": 5 }));
+        assert_eq!(console("/api/console?since=5"), json!({ "lines": [], "next": 5 }));
+        for bad in ["/api/console?lines=ten", "/api/console?since=-1", "/api/console/stream?lines=1.5"] {
+            assert_eq!(call(&shared, Method::Get, bad, "").0, 400, "{}", bad);
+        }
+
+        let mut stream = request(Method::Get, "/api/console/stream?since=2&lines=5", Some(&format!("Bearer {}", TOKEN)), "");
+        assert!(matches!(handled(&mut stream, &shared), Handled::Stream { since: 2, limit: 5 }));
+        let mut stream = request(Method::Get, "/api/console/stream", Some(&format!("Bearer {}", TOKEN)), "");
+        assert!(matches!(handled(&mut stream, &shared), Handled::Stream { since: 0, limit: DEFAULT_CONSOLE_LINES }));
+        let mut stream = request(Method::Get, "/api/console/stream", None, "");
+        assert!(matches!(handled(&mut stream, &shared), Handled::Respond(response) if response.status_code().0 == 401));
+    }
+}
@@ -1,32 +1,512 @@
 use std::{
-    io::Error,
-    process::{Command, ExitStatus},
+    error::Error,
+    fmt::{self, Display},
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime},
 };
 
-use chrono::Local;
-use tracing::info;
-
-pub fn start_backup(dir: &String, file_name: &String) -> Result<ExitStatus, Error> {
-    let curr_date = Local::now().format("%m.%d.%Y").to_string();
-    Command::new("zip")
-        .args([
-            "-vr",
-            format!("./{} {}.zip", file_name, curr_date).as_str(),
-            dir,
-        ])
-        .spawn()
-        .expect("Should be able to spawn zip process")
-        .wait()
-        .expect("Failed to zip server folder");
-    info!("Finished zipping server folder into archive \"{} {}.zip\". Now starting Google Drive upload, this may take ahwile.", file_name, curr_date);
-    Command::new("rclone")
-        .args([
-            "copy",
-            "--update",
-            format!("{} {}.zip", file_name, curr_date).as_str(),
-            "gdrive:Minecraft-Servers/",
-        ])
-        .spawn()
-        .expect("Should be able to spawn rclone process")
-        .wait()
+use chrono::{DateTime, Datelike, Local, Timelike};
+use flate2::{write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+use walkdir::WalkDir;
+use zip::{result::ZipError, write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+use crate::{config::{BackupMode, Config}, process::Process, restore, retention, target::TargetError};
+
+/// how often progress is logged while archiving
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    #[default]
+    #[serde(rename = "zip")]
+    Zip,
+    #[serde(rename = "tar.gz")]
+    TarGz,
+    #[serde(rename = "tar.zst")]
+    TarZst,
+}
+
+impl ArchiveFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::TarGz => "tar.gz",
+            ArchiveFormat::TarZst => "tar.zst",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum BackupError {
+    /// reading something from the server folder or writing the archive failed
+    Io { path: PathBuf, source: io::Error },
+    /// the zip writer failed on this file
+    Zip { path: PathBuf, source: ZipError },
+    /// the server never confirmed `save-all flush` during an online backup
+    SaveTimeout(Duration),
+}
+
+impl Display for BackupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackupError::Io { path, source } => write!(f, "failed to archive {}: {}", path.display(), source),
+            BackupError::Zip { path, source } => write!(f, "failed to zip {}: {}", path.display(), source),
+            BackupError::SaveTimeout(timeout) => write!(f, "the server didn't confirm `save-all flush` within {} seconds", timeout.as_secs()),
+        }
+    }
+}
+
+impl Error for BackupError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BackupError::Io { source, .. } => Some(source),
+            BackupError::Zip { source, .. } => Some(source),
+            BackupError::SaveTimeout(_) => None,
+        }
+    }
+}
+
+/// What went into a finished archive
+#[derive(Debug)]
+pub struct Archive {
+    pub path: PathBuf,
+    pub files: u64,
+    /// size of the archived files before compression
+    pub bytes: u64,
+    /// size of the archive itself
+    pub archive_bytes: u64,
+    /// how the upload to each of the `backup_targets` went
+    pub uploads: Vec<Upload>,
+}
+
+#[derive(Debug)]
+pub struct Upload {
+    pub target: String,
+    pub result: Result<(), TargetError>,
+}
+
+/// A backup in progress, moved along by the control loop so the bouncer keeps responding.
+///
+/// An online backup turns saving off and flushes the world to disk before archiving so the files don't change underneath us,
+/// an offline one stops the server. The archive is made and uploaded on its own thread, and the server is let go as soon as the
+/// archive is made, before the uploads finish. Saving is turned back on, or the server is started again
+pub struct BackupJob {
+    state: BackupState,
+    mode: BackupMode,
+    started: Instant,
+    /// the server was started again after an offline backup and the control loop hasn't been told yet
+    restarted: bool,
+}
+
+enum BackupState {
+    /// waiting for the server to confirm `save-all flush`
+    Saving { config: Box<Config>, timeout: Duration, deadline: Instant },
+    /// waiting for the server to stop for an offline backup
+    Stopping { config: Box<Config> },
+    /// archiving then uploading on the backup thread, which sends on `archived` once the archive is made
+    Storing { archived: Receiver<()>, holding: bool, thread: Option<JoinHandle<Result<Archive, BackupError>>> },
+}
+
+impl BackupJob {
+    /// Stops the server then backs it up, starting it again once the archive is made
+    pub fn offline(process: &mut Process, config: &Config) -> Self {
+        info!("Stopping the server for the backup");
+        process.stop();
+        Self {
+            state: BackupState::Stopping { config: Box::new(config.clone()) },
+            mode: BackupMode::Offline,
+            started: Instant::now(),
+            restarted: false,
+        }
+    }
+
+    /// Backs up the server while it keeps running
+    pub fn online(process: &mut Process, config: &Config) -> Self {
+        info!("Turning off automatic saving and flushing the world to disk");
+        let started = Instant::now();
+        process.cmd("save-off".to_string());
+        // over RCON the confirmation is the reply rather than a line of server output, and a large world can take
+        // longer to flush than a normal command gets to reply
+        let timeout = Duration::from_secs(config.backup_save_timeout);
+        let saved = process.send_waiting("save-all flush", timeout).is_some_and(|reply| reply.contains("Saved the game"));
+        let mut backup = Self {
+            // the wait for the RCON reply counts towards the timeout
+            state: BackupState::Saving { config: Box::new(config.clone()), timeout, deadline: started + timeout },
+            mode: BackupMode::Online,
+            started,
+            restarted: false,
+        };
+        if saved {
+            backup.saved();
+        }
+        backup
+    }
+
+    /// how long it has been since the backup started, including waiting for the save
+    pub fn took(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// whether the server was started again after an offline backup since the last time this was called
+    pub fn take_restarted(&mut self) -> bool {
+        std::mem::take(&mut self.restarted)
+    }
+
+    /// Starts archiving, called when the server confirms `save-all flush`
+    pub fn saved(&mut self) {
+        let BackupState::Saving { config, .. } = &self.state else {
+            return;
+        };
+        info!("World saved, starting backup");
+        let config = config.clone();
+        self.store(config);
+    }
+
+    /// Archives then uploads on the backup thread
+    fn store(&mut self, config: Box<Config>) {
+        let (archived_tx, archived) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("backup".to_string())
+            .spawn(move || {
+                let result = create_backup(&config);
+                let _ = archived_tx.send(());
+                store_backup(&config, result?)
+            })
+            .unwrap();
+        self.state = BackupState::Storing { archived, holding: true, thread: Some(thread) };
+    }
+
+    /// Whether the server has to stay as it is until the world is archived, running with saving off or stopped
+    pub fn holds_server(&self) -> bool {
+        match &self.state {
+            BackupState::Saving { .. } | BackupState::Stopping { .. } => true,
+            BackupState::Storing { holding, .. } => *holding,
+        }
+    }
+
+    /// Lets the server go once it can be and returns how the backup went once it has finished
+    pub fn poll(&mut self, process: &mut Process) -> Option<Result<Archive, BackupError>> {
+        match &mut self.state {
+            BackupState::Stopping { config } => {
+                if process.stopped() {
+                    info!("Server stopped, starting backup");
+                    let config = config.clone();
+                    self.store(config);
+                }
+                None
+            },
+            BackupState::Saving { timeout, deadline, .. } => {
+                if Instant::now() < *deadline {
+                    return None;
+                }
+                error!("Server didn't confirm the save within {} seconds, skipping backup", timeout.as_secs());
+                turn_saving_on(process);
+                Some(Err(BackupError::SaveTimeout(*timeout)))
+            }
+            BackupState::Storing { archived, holding, thread } => {
+                if *holding && archived.try_recv() != Err(TryRecvError::Empty) {
+                    match self.mode {
+                        BackupMode::Online => turn_saving_on(process),
+                        BackupMode::Offline => {
+                            info!("Starting the server again, the backup keeps uploading");
+                            process.restart();
+                            self.restarted = true;
+                        },
+                    }
+                    *holding = false;
+                }
+                if !thread.as_ref().is_some_and(|thread| thread.is_finished()) {
+                    return None;
+                }
+                thread.take().map(|thread| thread.join().expect("the backup thread doesn't panic"))
+            }
+        }
+    }
+
+    /// Waits for the backup to finish, for when the bouncer is exiting. A backup that hasn't started archiving is dropped
+    pub fn finish(mut self) -> Option<Result<Archive, BackupError>> {
+        match &mut self.state {
+            BackupState::Saving { .. } => {
+                warn!("Abandoning the online backup, the server never confirmed the save");
+                None
+            }
+            BackupState::Stopping { .. } => {
+                warn!("Abandoning the offline backup, the server hadn't stopped yet");
+                None
+            }
+            BackupState::Storing { thread, .. } => {
+                info!("Waiting for the backup to finish uploading");
+                thread.take().map(|thread| thread.join().expect("the backup thread doesn't panic"))
+            }
+        }
+    }
+}
+
+fn turn_saving_on(process: &mut Process) {
+    process.cmd("save-on".to_string());
+    info!("Automatic saving turned back on");
+}
+
+/// Archives the server folder then uploads it to every target. The server should not be running
+pub fn start_backup(config: &Config) -> Result<Archive, BackupError> {
+    store_backup(config, create_backup(config)?)
+}
+
+/// Uploads a new archive to every target, adds it to the restore index then prunes the backup folder.
+/// Failed uploads are recorded in the archive and a failed prune is only logged
+fn store_backup(config: &Config, mut archive: Archive) -> Result<Archive, BackupError> {
+    for target in &config.backup_targets {
+        let result = target.upload(&archive.path);
+        match &result {
+            Ok(_) => info!("Uploaded {} to {}", archive.path.display(), target),
+            Err(err) => error!("Failed to upload {} to {}: {}", archive.path.display(), target, err),
+        }
+        archive.uploads.push(Upload {
+            target: target.to_string(),
+            result,
+        });
+    }
+    restore::record(config, &archive);
+    if let Err(io_err) = retention::prune(Path::new(&config.backup_folder), &config.backup_file_name, &config.backup_retention) {
+        warn!("Failed to prune old backups in {}: {}", config.backup_folder, io_err);
+    }
+    Ok(archive)
+}
+
+/// archives the server folder into `backup_folder` using the configured format
+pub fn create_backup(config: &Config) -> Result<Archive, BackupError> {
+    let folder = Path::new(&config.backup_folder);
+    fs::create_dir_all(folder).map_err(|source| BackupError::Io { path: folder.to_path_buf(), source })?;
+    let path = folder.join(retention::archive_name(
+        &config.backup_file_name,
+        Local::now().naive_local(),
+        config.backup_format,
+    ));
+    create_archive(
+        Path::new(&config.server_folder),
+        &path,
+        config.backup_format,
+        config.backup_compression_level,
+    )
+}
+
+/// Archives everything inside `dir` into a new archive at `path`. Paths inside the archive are relative to `dir`.
+///
+/// `level` uses the format's own scale, 0-9 for zip and tar.gz and 1-22 for tar.zst. `None` picks the format's default
+pub fn create_archive(dir: &Path, path: &Path, format: ArchiveFormat, level: Option<i32>) -> Result<Archive, BackupError> {
+    info!("Archiving {} into {}", dir.display(), path.display());
+    let io_err = |path: &Path| {
+        let path = path.to_path_buf();
+        move |source| BackupError::Io { path, source }
+    };
+
+    let file = BufWriter::new(File::create(path).map_err(io_err(path))?);
+    let mut progress = Progress::new();
+
+    match format {
+        ArchiveFormat::Zip => {
+            let mut zip = ZipWriter::new(file);
+            let options = SimpleFileOptions::default()
+                .compression_method(CompressionMethod::Deflated)
+                .compression_level(level.map(i64::from));
+            for (full_path, name, len) in walk(dir)? {
+                let zip_err = |source| BackupError::Zip { path: full_path.clone(), source };
+                match len {
+                    None => zip.add_directory(name, options).map_err(zip_err)?,
+                    Some(len) => {
+                        let mut source = File::open(&full_path).map_err(io_err(&full_path))?;
+                        let mut file_options = options.large_file(len >= u32::MAX as u64);
+                        if let Some(modified) = source.metadata().and_then(|meta| meta.modified()).ok().and_then(zip_time) {
+                            file_options = file_options.last_modified_time(modified);
+                        }
+                        zip.start_file(name, file_options).map_err(zip_err)?;
+                        io::copy(&mut source, &mut zip).map_err(io_err(&full_path))?;
+                        progress.add(len);
+                    }
+                }
+            }
+            zip.finish()
+                .map_err(|source| BackupError::Zip { path: path.to_path_buf(), source })?
+                .flush()
+                .map_err(io_err(path))?;
+        }
+        ArchiveFormat::TarGz => {
+            let level = level.map_or(Compression::default(), |level| Compression::new(level.clamp(0, 9) as u32));
+            let encoder = write_tar(GzEncoder::new(file, level), dir, &mut progress)?;
+            encoder.finish().and_then(|mut file| file.flush()).map_err(io_err(path))?;
+        }
+        ArchiveFormat::TarZst => {
+            // 0 tells zstd to use its default level
+            let encoder = zstd::Encoder::new(file, level.unwrap_or(0)).map_err(io_err(path))?;
+            let encoder = write_tar(encoder, dir, &mut progress)?;
+            encoder.finish().and_then(|mut file| file.flush()).map_err(io_err(path))?;
+        }
+    }
+
+    let archive_bytes = fs::metadata(path).map_err(io_err(path))?.len();
+    info!(
+        "Finished archiving {} files ({}) into {} ({})",
+        progress.files,
+        format_bytes(progress.bytes),
+        path.display(),
+        format_bytes(archive_bytes)
+    );
+    Ok(Archive {
+        path: path.to_path_buf(),
+        files: progress.files,
+        bytes: progress.bytes,
+        archive_bytes,
+        uploads: Vec::new(),
+    })
+}
+
+/// zip stores local time with no timezone, and can't go before 1980
+fn zip_time(time: SystemTime) -> Option<zip::DateTime> {
+    let time = DateTime::<Local>::from(time).naive_local();
+    zip::DateTime::from_date_and_time(
+        time.year().try_into().ok()?,
+        time.month() as u8,
+        time.day() as u8,
+        time.hour() as u8,
+        time.minute() as u8,
+        time.second() as u8,
+    )
+    .ok()
+}
+
+fn write_tar<W: Write>(writer: W, dir: &Path, progress: &mut Progress) -> Result<W, BackupError> {
+    let mut tar = tar::Builder::new(writer);
+    for (full_path, name, len) in walk(dir)? {
+        let io_err = |source| BackupError::Io { path: full_path.clone(), source };
+        match len {
+            None => tar.append_dir(&name, &full_path).map_err(io_err)?,
+            Some(len) => {
+                tar.append_path_with_name(&full_path, &name).map_err(io_err)?;
+                progress.add(len);
+            }
+        }
+    }
+    tar.into_inner().map_err(|source| BackupError::Io { path: dir.to_path_buf(), source })
+}
+
+/// Lists every file and folder inside `dir` as `(full path, path inside the archive, file size)`.
+/// Folders have no size. Anything that isn't a plain file or folder is skipped
+fn walk(dir: &Path) -> Result<Vec<(PathBuf, String, Option<u64>)>, BackupError> {
+    let mut entries = Vec::new();
+    for entry in WalkDir::new(dir).min_depth(1).sort_by_file_name() {
+        let entry = entry.map_err(|err| BackupError::Io {
+            path: err.path().unwrap_or(dir).to_path_buf(),
+            source: err.into(),
+        })?;
+        // archive paths always use `/` no matter the platform
+        let name = entry
+            .path()
+            .strip_prefix(dir)
+            .expect("walkdir entries are inside the walked folder")
+            .components()
+            .map(|part| part.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        let file_type = entry.file_type();
+        if file_type.is_dir() {
+            entries.push((entry.into_path(), name, None));
+        } else if file_type.is_file() {
+            let len = entry
+                .metadata()
+                .map_err(|err| BackupError::Io { path: entry.path().to_path_buf(), source: err.into() })?
+                .len();
+            entries.push((entry.into_path(), name, Some(len)));
+        } else {
+            warn!("Skipping {} since it isn't a file or folder", entry.path().display());
+        }
+    }
+    Ok(entries)
+}
+
+/// Counts what has been archived so far and logs it every now and then
+struct Progress {
+    files: u64,
+    bytes: u64,
+    last_report: Instant,
+}
+
+impl Progress {
+    fn new() -> Self {
+        Self {
+            files: 0,
+            bytes: 0,
+            last_report: Instant::now(),
+        }
+    }
+
+    fn add(&mut self, len: u64) {
+        self.files += 1;
+        self.bytes += len;
+        if self.last_report.elapsed() >= PROGRESS_INTERVAL {
+            info!("Archived {} files ({}) so far", self.files, format_bytes(self.bytes));
+            self.last_report = Instant::now();
+        }
+    }
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        return format!("{} {}", bytes, UNITS[0]);
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// every file and folder inside `dir` with the contents of the files, `None` for folders
+    fn tree(dir: &Path) -> Vec<(String, Option<String>)> {
+        walk(dir)
+            .unwrap()
+            .into_iter()
+            .map(|(full_path, name, len)| (name, len.map(|_| fs::read_to_string(full_path).unwrap())))
+            .collect()
+    }
+
+    #[test]
+    fn archives_round_trip() {
+        let root = std::env::temp_dir().join(format!("bouncer_backup_test_{}", std::process::id()));
+        let server = root.join("server");
+        fs::create_dir_all(server.join("world/region")).unwrap();
+        fs::create_dir_all(server.join("world/empty")).unwrap();
+        fs::write(server.join("server.properties"), "level-name=world\n").unwrap();
+        fs::write(server.join("world/level.dat"), "level").unwrap();
+        fs::write(server.join("world/region/r.0.0.mca"), "region".repeat(1000)).unwrap();
+        let expected = tree(&server);
+        assert_eq!(
+            expected.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(),
+            ["server.properties", "world", "world/empty", "world/level.dat", "world/region", "world/region/r.0.0.mca"]
+        );
+
+        for format in [ArchiveFormat::Zip, ArchiveFormat::TarGz, ArchiveFormat::TarZst] {
+            let path = root.join(format!("backup.{}", format.extension()));
+            let archive = create_archive(&server, &path, format, None).unwrap();
+            assert_eq!((archive.files, archive.bytes), (3, 6022), "{}", format.extension());
+
+            let extracted = root.join(format!("extracted {}", format.extension()));
+            restore::extract(&path, format, &extracted).unwrap();
+            assert_eq!(tree(&extracted), expected, "{}", format.extension());
+        }
+        fs::remove_dir_all(&root).unwrap();
+    }
 }
@@ -0,0 +1,45 @@
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+use tracing::Level;
+
+/// Starts and manages a minecraft server with automatic restarts, backups and alerts
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// the config file to use
+    #[arg(short, long, global = true, default_value = "config/server_bounce_config.json")]
+    pub config: PathBuf,
+    /// the least important messages that get logged: error, warn, info, debug or trace
+    #[arg(long, global = true, default_value = "info")]
+    pub log_level: Level,
+    /// log what would be done without starting the server or touching any files
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+    /// what to do, `run` if left out
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// start the server and keep it running, restarting and backing it up on schedule
+    Run,
+    /// check the config and print every problem with it
+    CheckConfig,
+    /// back up the server folder and upload it to every target. Refused while a bouncer or the server is running
+    BackupNow,
+    /// list the backups, or roll the server folder back to one. Restoring is refused while a bouncer or the server is running
+    Restore {
+        /// the backup to restore, from the list printed when this is left out
+        id: Option<String>,
+    },
+    /// print a config with every field filled in to start from
+    PrintDefaultConfig,
+    /// send a console command like `restart -t 600` or `status --json` to the running bouncer and print its reply
+    Ctl {
+        /// the command, the same as it would be typed into the console
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+}
@@ -1,34 +1,773 @@
-use std::{path::Path, fs::read_to_string};
+use std::{cmp::Reverse, error::Error, fmt::{self, Display}, io, path::{Path, PathBuf}, fs::{self, read_to_string}, time::{Duration, SystemTime}};
+use chrono::NaiveTime;
 use serde::{Serialize, Deserialize};
+use tracing::warn;
 
-#[derive(Serialize, Deserialize)]
+use crate::{api::ApiConfig, backup::ArchiveFormat, control::ControlConfig, notify::NotifyConfig, process::{Launch, StopTimeouts}, retention::Retention, schedule::ScheduleConfig, target::{default_targets, BackupTarget}};
+
+/// fields only used to start the server, so a reload picks them up the next time it restarts
+pub const RESTART_ONLY_FIELDS: [&str; 7] = [
+    "launch_mode",
+    "server_start_file",
+    "jar_file_name",
+    "java_binary",
+    "java_args",
+    "nogui",
+    "server_folder",
+];
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Config {
     pub restart_duration: u64,
-    pub restart_warning_msgs: Vec<RestartWarning>,
+    /// messages said to the players before every restart
+    #[serde(default)]
+    pub restart_warnings: Vec<RestartWarning>,
+    /// the old way of writing the warnings where each `time` counts from the warning before it, still read from older configs
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub restart_warning_msgs: Vec<ChainedWarning>,
+    #[serde(default)]
+    pub launch_mode: LaunchMode,
     pub server_start_file: String,
+    /// the server jar to run when `launch_mode` is `java`
+    #[serde(default)]
+    pub jar_file_name: String,
+    /// the java binary to run when `launch_mode` is `java`
+    #[serde(default = "default_java_binary")]
+    pub java_binary: String,
     pub server_folder: String,
     pub backup_file_name: String,
+    /// where backup archives are made and kept
+    #[serde(default = "default_backup_folder")]
+    pub backup_folder: String,
+    #[serde(default)]
+    pub backup_retention: Retention,
+    /// everywhere a copy of each backup is uploaded to
+    #[serde(default = "default_targets")]
+    pub backup_targets: Vec<BackupTarget>,
     pub backup_time: String,
+    #[serde(default)]
+    pub backup_mode: BackupMode,
+    #[serde(default)]
+    pub backup_format: ArchiveFormat,
+    /// compression level on the format's own scale, left out for the format's default
+    #[serde(default)]
+    pub backup_compression_level: Option<i32>,
+    /// seconds to wait for the server to confirm `save-all flush` during an online backup
+    #[serde(default = "default_backup_save_timeout")]
+    pub backup_save_timeout: u64,
     pub java_args: Args,
-    pub nogui: bool
+    pub nogui: bool,
+    /// seconds to wait for the server to exit on its own after sending `stop` before sending SIGTERM
+    #[serde(default = "default_stop_timeout")]
+    pub stop_timeout: u64,
+    /// seconds to wait after SIGTERM before the server is forcefully killed
+    #[serde(default = "default_terminate_timeout")]
+    pub terminate_timeout: u64,
+    #[serde(default)]
+    pub crash_backoff: CrashBackoff,
+    /// where a folder is created for every unexpected server exit
+    #[serde(default = "default_incident_folder")]
+    pub incident_folder: String,
+    /// how many lines of server output get saved with an incident
+    #[serde(default = "default_incident_log_lines")]
+    pub incident_log_lines: usize,
+    /// where alerts about crashes and backups are sent
+    #[serde(default)]
+    pub notify: NotifyConfig,
+    /// when restarts, backups, commands and broadcasts happen
+    #[serde(default)]
+    pub schedule: ScheduleConfig,
+    /// the socket `ctl` sends commands through
+    #[serde(default)]
+    pub control: ControlConfig,
+    /// the HTTP API for web panels and scripts
+    #[serde(default)]
+    pub api: ApiConfig,
 }
 
 impl Config {
-    pub fn read<P>(path: P) -> Self 
-        where P: AsRef<Path> + std::fmt::Display
-        {
-        let config_data = read_to_string(path).expect("Failed To Read Config File. Make Sure You Have `config/server_bounce_config.json` in your root directory");
+    /// Reads the config and checks every field, so a bad value is caught at startup instead of breaking the server later
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let path = path.as_ref().to_path_buf();
+        let config_data = read_to_string(&path).map_err(|source| ConfigError::Read { path: path.clone(), source })?;
+
+        let config = serde_path_to_error::deserialize::<_, Config>(&mut serde_json::Deserializer::from_str(&config_data)).map_err(|err| {
+            // syntax errors like a missing bracket and missing top level fields aren't inside any one field
+            let field = Some(err.path().to_string()).filter(|field| err.inner().is_data() && field != ".");
+            ConfigError::Parse { path: path.clone(), field, source: err.into_inner() }
+        })?;
+
+        let problems = config.validate();
+        if !problems.0.is_empty() {
+            return Err(ConfigError::Invalid { path, problems: problems.0 });
+        }
+        if !config.restart_warning_msgs.is_empty() {
+            warn!("`restart_warning_msgs` is deprecated, see the readme for how to write them as `restart_warnings`");
+        }
+        Ok(config)
+    }
+
+    fn validate(&self) -> Problems {
+        let mut problems = Problems::default();
+
+        if self.restart_duration == 0 {
+            problems.add("restart_duration", "must be more than 0 seconds");
+        }
+        let warnings = self.restart_warning_msgs.iter().map(|warning| warning.time).sum::<u64>();
+        if warnings > self.restart_duration {
+            problems.add(
+                "restart_warning_msgs",
+                format!("the warning times add up to {} seconds but the server restarts after {} seconds (`restart_duration`)", warnings, self.restart_duration),
+            );
+        }
+        if !self.restart_warning_msgs.is_empty() && !self.restart_warnings.is_empty() {
+            problems.add("restart_warning_msgs", "can't be used along with `restart_warnings`, move them over to `restart_warnings`");
+        }
+        for (i, warning) in self.restart_warnings.iter().enumerate() {
+            if warning.before == 0 {
+                problems.add(format!("restart_warnings[{}].before", i), "must be more than 0 seconds");
+            } else if self.schedule.restart.is_none() && warning.before > self.restart_duration {
+                problems.add(
+                    format!("restart_warnings[{}].before", i),
+                    format!("{} seconds is before the server even started, it restarts after {} seconds (`restart_duration`)", warning.before, self.restart_duration),
+                );
+            }
+            if warning.every == Some(0) {
+                problems.add(format!("restart_warnings[{}].every", i), "must be more than 0 seconds");
+            }
+            if warning.msg.trim().is_empty() {
+                problems.add(format!("restart_warnings[{}].msg", i), "can't be empty");
+            }
+        }
+
+        if !Path::new(&self.server_folder).is_dir() {
+            problems.add("server_folder", format!("{} isn't a folder", self.server_folder));
+        }
+        let (field, file_name, mode) = match self.launch_mode {
+            LaunchMode::Script => ("server_start_file", &self.server_start_file, "script"),
+            LaunchMode::Java => ("jar_file_name", &self.jar_file_name, "java"),
+        };
+        if file_name.is_empty() {
+            problems.add(field, format!("is needed when `launch_mode` is `{}`", mode));
+        } else if !Path::new(&self.server_folder).join(file_name).is_file() {
+            problems.add(field, format!("{} doesn't exist in {}", file_name, self.server_folder));
+        }
+        if self.launch_mode == LaunchMode::Java && self.java_binary.is_empty() {
+            problems.add("java_binary", "can't be empty");
+        }
+
+        if self.backup_file_name.is_empty() || self.backup_file_name.contains(['/', '\\']) {
+            problems.add("backup_file_name", "must be a file name with no folders in it");
+        }
+        if let (Some(server), Some(backups)) = (resolve(Path::new(&self.server_folder)), resolve(Path::new(&self.backup_folder))) {
+            if backups.starts_with(&server) {
+                problems.add("backup_folder", "can't be inside `server_folder` or every backup would hold all the ones before it");
+            }
+        }
+        if self.backup_time().is_none() {
+            problems.add("backup_time", format!("{:?} isn't a 24 hour time like \"00:55\"", self.backup_time));
+        }
+        if let Some(level) = self.backup_compression_level {
+            let range = match self.backup_format {
+                ArchiveFormat::Zip | ArchiveFormat::TarGz => 0..=9,
+                ArchiveFormat::TarZst => 1..=22,
+            };
+            if !range.contains(&level) {
+                problems.add(
+                    "backup_compression_level",
+                    format!("{} is outside {}-{} which `{}` uses", level, range.start(), range.end(), self.backup_format.extension()),
+                );
+            }
+        }
+        if self.backup_save_timeout == 0 {
+            problems.add("backup_save_timeout", "must be more than 0 seconds");
+        }
+        for (i, target) in self.backup_targets.iter().enumerate() {
+            target.validate(&format!("backup_targets[{}]", i), &mut problems);
+        }
 
-        let config_data = serde_json::from_str::<Config>(config_data.as_str()).expect("Failed To Parse Data From Config File. Possibly Invalid Syntax");
+        let backoff = &self.crash_backoff;
+        if !backoff.multiplier.is_finite() || backoff.multiplier < 1.0 {
+            problems.add("crash_backoff.multiplier", "must be at least 1.0");
+        }
+        if backoff.initial_delay > backoff.max_delay {
+            problems.add("crash_backoff.initial_delay", "can't be more than `crash_backoff.max_delay`");
+        }
+        if backoff.max_crashes == 0 {
+            problems.add("crash_backoff.max_crashes", "must be at least 1");
+        }
 
-        config_data
+        self.notify.validate("notify", &mut problems);
+        self.schedule.validate("schedule", &mut problems);
+        self.control.validate("control", &mut problems);
+        self.api.validate("api", &mut problems);
+        problems
+    }
+
+    /// the names of the top level fields that are different in `other`
+    pub fn changed_fields(&self, other: &Config) -> Vec<String> {
+        let to_map = |config: &Config| match serde_json::to_value(config) {
+            Ok(serde_json::Value::Object(map)) => map,
+            _ => unreachable!("the config always serializes to an object"),
+        };
+        let (old, new) = (to_map(self), to_map(other));
+        new.into_iter().filter(|(field, value)| old.get(field) != Some(value)).map(|(field, _)| field).collect()
+    }
+
+    /// Every warning said before a restart, the one furthest from the restart first
+    pub fn warnings(&self) -> Vec<Warning> {
+        let mut warnings = Vec::new();
+        for warning in &self.restart_warnings {
+            let mut before = warning.before;
+            while before > 0 {
+                warnings.push(Warning { before, msg: warning.msg.replace("{remaining}", &format_remaining(before)) });
+                match warning.every {
+                    Some(every) if every > 0 => before = before.saturating_sub(every),
+                    _ => break,
+                }
+            }
+        }
+        // each `time` counted from the warning before it, starting when the server started
+        let mut before = self.restart_duration;
+        for warning in &self.restart_warning_msgs {
+            before = before.saturating_sub(warning.time);
+            warnings.push(Warning { before, msg: warning.msg.clone() });
+        }
+        // two warnings at the same time would be said at once, so only the first one written is kept
+        warnings.sort_by_key(|warning| Reverse(warning.before));
+        warnings.dedup_by_key(|warning| warning.before);
+        warnings
+    }
+
+    /// the time of day the automatic backup runs when `schedule.backup` isn't set, `None` if `backup_time` isn't valid
+    pub fn backup_time(&self) -> Option<NaiveTime> {
+        NaiveTime::parse_from_str(&self.backup_time, "%H:%M").ok()
+    }
+
+    pub fn stop_timeouts(&self) -> StopTimeouts {
+        StopTimeouts {
+            stop: Duration::from_secs(self.stop_timeout),
+            terminate: Duration::from_secs(self.terminate_timeout),
+        }
+    }
+
+    /// how the server should be started according to `launch_mode`
+    pub fn launch(&self) -> Launch {
+        match self.launch_mode {
+            LaunchMode::Script => Launch::Script(self.server_start_file.clone()),
+            LaunchMode::Java => Launch::Java {
+                java: self.java_binary.clone(),
+                jar: self.jar_file_name.clone(),
+                args: self.java_args.clone(),
+                nogui: self.nogui,
+            },
+        }
     }
 }
 
-#[derive(Serialize, Deserialize)]
+/// `path` made absolute with any `..` and links followed as far as it exists, since the backup folder may not have been made yet
+fn resolve(path: &Path) -> Option<PathBuf> {
+    let mut existing = std::path::absolute(path).ok()?;
+    let mut missing = Vec::new();
+    loop {
+        match existing.canonicalize() {
+            Ok(resolved) => return Some(missing.into_iter().rev().fold(resolved, |resolved, part: std::ffi::OsString| resolved.join(part))),
+            Err(_) => {
+                missing.push(existing.file_name()?.to_os_string());
+                existing = existing.parent()?.to_path_buf();
+            },
+        }
+    }
+}
+
+/// Notices when the config file is saved by checking when it was last modified
+pub struct ConfigWatcher {
+    path: PathBuf,
+    seen: Option<(SystemTime, u64)>,
+    /// the file changed but might still be being written
+    settling: bool,
+}
+
+impl ConfigWatcher {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let seen = Self::stamp(&path);
+        Self { path, seen, settling: false }
+    }
+
+    fn stamp(path: &Path) -> Option<(SystemTime, u64)> {
+        let meta = fs::metadata(path).ok()?;
+        Some((meta.modified().ok()?, meta.len()))
+    }
+
+    /// Whether the file changed since the last call. A change is only reported once the file
+    /// stays the same for a whole call, so an editor that is part way through saving isn't read
+    pub fn changed(&mut self) -> bool {
+        let stamp = Self::stamp(&self.path);
+        if stamp != self.seen {
+            self.seen = stamp;
+            self.settling = true;
+            return false;
+        }
+        std::mem::take(&mut self.settling) && stamp.is_some()
+    }
+}
+
+/// A starting point for a new config, printed by `print-default-config`. Fields every config needs get example values
+impl Default for Config {
+    fn default() -> Self {
+        let warning = |before, every| RestartWarning { before, every, msg: "Server restarting in {remaining}".to_string() };
+        Self {
+            // restart every 12 hours, warning 30, 10 and 1 minutes before then every second for the last 10 seconds
+            restart_duration: 43200,
+            restart_warnings: vec![warning(1800, None), warning(600, None), warning(60, None), warning(10, Some(1))],
+            restart_warning_msgs: Vec::new(),
+            launch_mode: LaunchMode::Java,
+            server_start_file: "run.sh".to_string(),
+            jar_file_name: "server.jar".to_string(),
+            java_binary: default_java_binary(),
+            server_folder: "server".to_string(),
+            backup_file_name: "server backup".to_string(),
+            backup_folder: default_backup_folder(),
+            backup_retention: Retention::default(),
+            backup_targets: default_targets(),
+            backup_time: "04:00".to_string(),
+            backup_mode: BackupMode::default(),
+            backup_format: ArchiveFormat::default(),
+            backup_compression_level: None,
+            backup_save_timeout: default_backup_save_timeout(),
+            java_args: vec!["-Xmx4G".to_string(), "-Xms4G".to_string()],
+            nogui: true,
+            stop_timeout: default_stop_timeout(),
+            terminate_timeout: default_terminate_timeout(),
+            crash_backoff: CrashBackoff::default(),
+            incident_folder: default_incident_folder(),
+            incident_log_lines: default_incident_log_lines(),
+            notify: NotifyConfig::default(),
+            schedule: ScheduleConfig::default(),
+            control: ControlConfig::default(),
+            api: ApiConfig::default(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LaunchMode {
+    /// run `server_start_file` with the shell
+    #[default]
+    Script,
+    /// run `java_binary` with `java_args`, `-jar <jar_file_name>` and `nogui`
+    Java,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct RestartWarning {
+    /// seconds before the restart the message is said
+    pub before: u64,
+    /// say it again every this many seconds until the restart, for a countdown
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub every: Option<u64>,
+    /// `{remaining}` is replaced with the time left like `5 minutes`
+    pub msg: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ChainedWarning {
     pub msg: String,
     pub time: u64
 }
 
+/// A single message said before a restart, with `{remaining}` filled in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    pub before: u64,
+    pub msg: String,
+}
+
+/// `seconds` in words like `1 hour 30 minutes`
+pub fn format_remaining(seconds: u64) -> String {
+    let parts = [(seconds / 3600, "hour"), (seconds / 60 % 60, "minute"), (seconds % 60, "second")];
+    let words = parts
+        .iter()
+        .filter(|(amount, _)| *amount > 0)
+        .map(|(amount, unit)| format!("{} {}{}", amount, unit, if *amount == 1 {""} else {"s"}))
+        .collect::<Vec<_>>();
+    if words.is_empty() {
+        return "0 seconds".to_string();
+    }
+    words.join(" ")
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BackupMode {
+    /// stop the server, back it up, then start it again
+    #[default]
+    Offline,
+    /// keep the server running and pause saving with `save-off` while the backup is made
+    Online,
+}
+
+/// How the server is restarted after it crashes
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct CrashBackoff {
+    /// seconds to wait before restarting after the first crash
+    pub initial_delay: u64,
+    /// how much the delay grows with every crash inside `window`
+    pub multiplier: f64,
+    /// the longest the delay can grow in seconds
+    pub max_delay: u64,
+    /// how many crashes inside `window` before we stop restarting the server
+    pub max_crashes: u32,
+    /// seconds a crash counts towards the backoff and `max_crashes`
+    pub window: u64,
+}
+
+impl CrashBackoff {
+    pub fn window(&self) -> Duration {
+        Duration::from_secs(self.window)
+    }
+
+    /// the delay before restarting after the `crashes`th crash inside the window
+    pub fn delay(&self, crashes: u32) -> Duration {
+        let exponent = crashes.saturating_sub(1) as i32;
+        let delay = self.initial_delay as f64 * self.multiplier.powi(exponent);
+        Duration::from_secs_f64(delay.min(self.max_delay as f64))
+    }
+}
+
+impl Default for CrashBackoff {
+    fn default() -> Self {
+        Self {
+            initial_delay: 5,
+            multiplier: 2.0,
+            max_delay: 300,
+            max_crashes: 5,
+            window: 600,
+        }
+    }
+}
+
 pub type Args = Vec<String>;
+
+/// Why the config couldn't be loaded
+#[derive(Debug)]
+pub enum ConfigError {
+    Read { path: PathBuf, source: io::Error },
+    /// the file isn't valid json or `field` has the wrong type
+    Parse { path: PathBuf, field: Option<String>, source: serde_json::Error },
+    /// every field that has a bad value
+    Invalid { path: PathBuf, problems: Vec<Problem> },
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read { path, source } => write!(
+                f,
+                "Failed To Read Config File {}: {}. Make Sure It Exists Or Pass Another One With `--config`",
+                path.display(),
+                source
+            ),
+            ConfigError::Parse { path, field: Some(field), source } => write!(f, "Failed To Parse Config File {}: `{}`: {}", path.display(), field, source),
+            ConfigError::Parse { path, field: None, source } => write!(f, "Failed To Parse Config File {}: {}", path.display(), source),
+            ConfigError::Invalid { path, problems } => {
+                write!(f, "Config File {} Has {} Problem{}:", path.display(), problems.len(), if problems.len() == 1 {""} else {"s"})?;
+                for problem in problems {
+                    write!(f, "\n    {}", problem)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConfigError::Read { source, .. } => Some(source),
+            ConfigError::Parse { source, .. } => Some(source),
+            ConfigError::Invalid { .. } => None,
+        }
+    }
+}
+
+/// A bad value found while validating the config
+#[derive(Debug)]
+pub struct Problem {
+    /// where the value is, like `backup_targets[0].bucket`
+    pub field: String,
+    pub msg: String,
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}`: {}", self.field, self.msg)
+    }
+}
+
+/// Collects every problem in the config so they can all be reported at once
+#[derive(Default)]
+pub struct Problems(Vec<Problem>);
+
+impl Problems {
+    pub fn add(&mut self, field: impl Into<String>, msg: impl Into<String>) {
+        self.0.push(Problem { field: field.into(), msg: msg.into() });
+    }
+}
+
+fn default_backup_folder() -> String {
+    "backups".to_string()
+}
+
+fn default_backup_save_timeout() -> u64 {
+    120
+}
+
+fn default_java_binary() -> String {
+    "java".to_string()
+}
+
+fn default_stop_timeout() -> u64 {
+    120
+}
+
+fn default_terminate_timeout() -> u64 {
+    30
+}
+
+fn default_incident_folder() -> String {
+    "incidents".to_string()
+}
+
+fn default_incident_log_lines() -> usize {
+    200
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schedule::When;
+
+    fn warning(before: u64, every: Option<u64>, msg: &str) -> RestartWarning {
+        RestartWarning { before, every, msg: msg.to_string() }
+    }
+
+    fn chained(time: u64, msg: &str) -> ChainedWarning {
+        ChainedWarning { msg: msg.to_string(), time }
+    }
+
+    /// when each warning is said and what it says
+    fn said(config: &Config) -> Vec<(u64, String)> {
+        config.warnings().into_iter().map(|warning| (warning.before, warning.msg)).collect()
+    }
+
+    #[test]
+    fn warnings_are_said_furthest_from_the_restart_first() {
+        let config = Config {
+            restart_warnings: vec![warning(60, None, "in {remaining}"), warning(1800, None, "in {remaining}"), warning(600, None, "in {remaining}")],
+            ..Config::default()
+        };
+        assert_eq!(
+            said(&config),
+            [(1800, "in 30 minutes".to_string()), (600, "in 10 minutes".to_string()), (60, "in 1 minute".to_string())]
+        );
+    }
+
+    #[test]
+    fn every_repeats_a_warning_until_the_restart() {
+        let config = Config { restart_warnings: vec![warning(3, Some(1), "{remaining}")], ..Config::default() };
+        assert_eq!(said(&config), [(3, "3 seconds".to_string()), (2, "2 seconds".to_string()), (1, "1 second".to_string())]);
+
+        // a step that doesn't divide `before` stops short of the restart
+        let config = Config { restart_warnings: vec![warning(25, Some(10), "{remaining}")], ..Config::default() };
+        assert_eq!(said(&config).iter().map(|(before, _)| *before).collect::<Vec<_>>(), [25, 15, 5]);
+    }
+
+    #[test]
+    fn warnings_at_the_same_time_keep_the_first_one_written() {
+        let config = Config {
+            restart_warnings: vec![warning(60, None, "first"), warning(120, Some(30), "countdown"), warning(60, None, "second")],
+            ..Config::default()
+        };
+        assert_eq!(
+            said(&config),
+            [(120, "countdown".to_string()), (90, "countdown".to_string()), (60, "first".to_string()), (30, "countdown".to_string())]
+        );
+    }
+
+    #[test]
+    fn chained_warnings_count_from_the_one_before() {
+        let config = Config {
+            restart_duration: 3600,
+            restart_warnings: Vec::new(),
+            restart_warning_msgs: vec![chained(1800, "half way"), chained(1200, "10 minutes"), chained(540, "1 minute")],
+            ..Config::default()
+        };
+        assert_eq!(
+            said(&config),
+            [(1800, "half way".to_string()), (600, "10 minutes".to_string()), (60, "1 minute".to_string())]
+        );
+    }
+
+    /// the fields `validate` finds a problem with
+    fn problems(config: &Config) -> Vec<String> {
+        config.validate().0.into_iter().map(|problem| problem.field).collect()
+    }
+
+    /// the fields with a problem, leaving out the server folder which doesn't exist in the default config
+    fn restart_problems(config: &Config) -> Vec<String> {
+        problems(config).into_iter().filter(|field| field.starts_with("restart_")).collect()
+    }
+
+    /// A server folder holding `run.sh` and `server.jar`, removed when dropped
+    struct ServerFolder(PathBuf);
+
+    impl ServerFolder {
+        fn new(name: &str) -> Self {
+            let folder = std::env::temp_dir().join(format!("bouncer_config_test_{}_{}", std::process::id(), name));
+            fs::create_dir_all(&folder).unwrap();
+            fs::write(folder.join("run.sh"), "java -jar server.jar nogui\n").unwrap();
+            fs::write(folder.join("server.jar"), "").unwrap();
+            Self(folder)
+        }
+
+        /// the default config with this as its `server_folder`
+        fn config(&self) -> Config {
+            Config { server_folder: self.0.to_string_lossy().to_string(), ..Config::default() }
+        }
+    }
+
+    impl Drop for ServerFolder {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn the_default_config_is_valid_with_a_server_folder() {
+        let folder = ServerFolder::new("default");
+        assert!(problems(&folder.config()).is_empty());
+        assert!(problems(&Config { launch_mode: LaunchMode::Script, ..folder.config() }).is_empty());
+    }
+
+    #[test]
+    fn every_problem_is_found_at_once() {
+        let folder = ServerFolder::new("every");
+        let config = Config {
+            restart_duration: 0,
+            backup_file_name: "backups/server".to_string(),
+            backup_time: "25:00".to_string(),
+            backup_save_timeout: 0,
+            crash_backoff: CrashBackoff { multiplier: 0.5, initial_delay: 600, max_delay: 300, max_crashes: 0, ..CrashBackoff::default() },
+            ..folder.config()
+        };
+        assert_eq!(
+            problems(&config),
+            [
+                "restart_duration",
+                "restart_warnings[0].before",
+                "restart_warnings[1].before",
+                "restart_warnings[2].before",
+                "restart_warnings[3].before",
+                "backup_file_name",
+                "backup_time",
+                "backup_save_timeout",
+                "crash_backoff.multiplier",
+                "crash_backoff.initial_delay",
+                "crash_backoff.max_crashes",
+            ]
+        );
+    }
+
+    #[test]
+    fn the_start_file_has_to_be_in_the_server_folder() {
+        let folder = ServerFolder::new("start_file");
+        let script = Config { launch_mode: LaunchMode::Script, server_start_file: "start.sh".to_string(), ..folder.config() };
+        assert_eq!(problems(&script), ["server_start_file"]);
+        // only the file for the launch mode in use is checked
+        assert_eq!(problems(&Config { jar_file_name: "missing.jar".to_string(), ..script.clone() }), ["server_start_file"]);
+
+        let java = Config { launch_mode: LaunchMode::Java, jar_file_name: String::new(), java_binary: String::new(), ..folder.config() };
+        assert_eq!(problems(&java), ["jar_file_name", "java_binary"]);
+
+        let missing = Config { server_folder: folder.0.join("missing").to_string_lossy().to_string(), ..folder.config() };
+        assert_eq!(problems(&missing), ["server_folder", "jar_file_name"]);
+    }
+
+    #[test]
+    fn backups_can_not_be_kept_in_the_server_folder() {
+        let folder = ServerFolder::new("backup_folder");
+        let inside = |backup_folder: PathBuf| problems(&Config { backup_folder: backup_folder.to_string_lossy().to_string(), ..folder.config() });
+        assert_eq!(inside(folder.0.join("backups")), ["backup_folder"]);
+        fs::create_dir(folder.0.join("world")).unwrap();
+        assert_eq!(inside(folder.0.join("world/../backups/new")), ["backup_folder"]);
+        assert_eq!(inside(folder.0.clone()), ["backup_folder"]);
+        assert!(inside(folder.0.with_file_name("bouncer_config_test_backups")).is_empty());
+    }
+
+    #[test]
+    fn chained_warnings_have_to_fit_in_the_restart_duration() {
+        let config = Config {
+            restart_duration: 600,
+            restart_warnings: Vec::new(),
+            restart_warning_msgs: vec![chained(500, "soon"), chained(90, "sooner")],
+            ..Config::default()
+        };
+        assert!(restart_problems(&config).is_empty());
+        let config = Config { restart_warning_msgs: vec![chained(500, "soon"), chained(101, "too late")], ..config };
+        assert_eq!(restart_problems(&config), ["restart_warning_msgs"]);
+        // the old and new way can't be mixed
+        let config = Config { restart_warnings: vec![warning(60, None, "in {remaining}")], restart_warning_msgs: vec![chained(60, "soon")], ..config };
+        assert_eq!(restart_problems(&config), ["restart_warning_msgs"]);
+    }
+
+    #[test]
+    fn restart_warnings_need_a_time_and_a_message() {
+        let config = Config { restart_warnings: vec![warning(60, Some(0), "  "), warning(30, Some(10), "ok")], ..Config::default() };
+        assert_eq!(restart_problems(&config), ["restart_warnings[0].every", "restart_warnings[0].msg"]);
+    }
+
+    #[test]
+    fn compression_levels_go_by_the_format() {
+        let folder = ServerFolder::new("compression");
+        let level = |backup_format, level| problems(&Config { backup_format, backup_compression_level: Some(level), ..folder.config() });
+        assert!(level(ArchiveFormat::Zip, 9).is_empty());
+        assert_eq!(level(ArchiveFormat::Zip, 10), ["backup_compression_level"]);
+        assert!(level(ArchiveFormat::TarGz, 0).is_empty());
+        assert!(level(ArchiveFormat::TarZst, 22).is_empty());
+        assert_eq!(level(ArchiveFormat::TarZst, 0), ["backup_compression_level"]);
+    }
+
+    #[test]
+    fn warnings_before_the_server_started_are_a_problem() {
+        let config = Config {
+            restart_duration: 600,
+            restart_warnings: vec![warning(600, None, "now"), warning(900, None, "too early"), warning(0, None, "too late")],
+            ..Config::default()
+        };
+        assert_eq!(restart_problems(&config), ["restart_warnings[1].before", "restart_warnings[2].before"]);
+
+        // `restart_duration` isn't used when the restart is scheduled
+        let config = Config {
+            schedule: ScheduleConfig { restart: Some(When::Times(vec!["04:00".to_string()])), ..ScheduleConfig::default() },
+            ..config
+        };
+        assert_eq!(restart_problems(&config), ["restart_warnings[2].before"]);
+    }
+
+    #[test]
+    fn format_remaining_spells_out_each_unit() {
+        assert_eq!(format_remaining(0), "0 seconds");
+        assert_eq!(format_remaining(1), "1 second");
+        assert_eq!(format_remaining(45), "45 seconds");
+        assert_eq!(format_remaining(60), "1 minute");
+        assert_eq!(format_remaining(61), "1 minute 1 second");
+        assert_eq!(format_remaining(3600), "1 hour");
+        assert_eq!(format_remaining(5400), "1 hour 30 minutes");
+        assert_eq!(format_remaining(7322), "2 hours 2 minutes 2 seconds");
+        assert_eq!(format_remaining(90000), "25 hours");
+    }
+}
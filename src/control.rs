@@ -0,0 +1,268 @@
+use std::{io, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config::Problems, input::Reply};
+
+/// Lets `ctl` send commands to the bouncer from another shell
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct ControlConfig {
+    /// where the socket is made, `null` to turn it off
+    pub socket: Option<String>,
+    /// permissions of the socket in octal. Anyone who can write to it can control the server
+    pub socket_mode: String,
+}
+
+impl Default for ControlConfig {
+    fn default() -> Self {
+        Self { socket: Some("server_bounce.sock".to_string()), socket_mode: "600".to_string() }
+    }
+}
+
+impl ControlConfig {
+    pub fn validate(&self, field: &str, problems: &mut Problems) {
+        if self.socket.as_ref().is_some_and(|socket| socket.is_empty()) {
+            problems.add(format!("{}.socket", field), "can't be empty, use null to turn the socket off");
+        }
+        if self.mode().is_none() {
+            problems.add(format!("{}.socket_mode", field), format!("{:?} isn't an octal file mode like \"600\" or \"660\"", self.socket_mode));
+        }
+    }
+
+    fn mode(&self) -> Option<u32> {
+        u32::from_str_radix(&self.socket_mode, 8).ok().filter(|mode| *mode <= 0o777)
+    }
+}
+
+/// The first line of every answer on the socket, followed by the text of the reply
+const DONE: &str = "ok";
+const FAILED: &str = "error";
+
+#[cfg(unix)]
+mod unix {
+    use std::{
+        fs::{self, DirBuilder, Permissions},
+        io::{self, BufRead, BufReader, Read, Write},
+        net::Shutdown,
+        os::unix::{fs::{DirBuilderExt, PermissionsExt}, net::{UnixListener, UnixStream}},
+        path::{Path, PathBuf},
+        sync::{mpsc::Sender, Arc, Mutex},
+        thread::{self, JoinHandle},
+        time::Duration,
+    };
+
+    use tracing::{error, info};
+
+    use super::{ControlConfig, DONE, FAILED};
+    use crate::input::{self, Input, Reply, Request};
+
+    /// how long a client gets to send its command, and the bouncer gets to answer it
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// Listens on the control socket and hands every command to the control loop. The socket is removed when this is dropped
+    pub struct ControlSocket {
+        path: PathBuf,
+        stopped: Arc<Mutex<bool>>,
+        listen_thread: Option<JoinHandle<()>>,
+    }
+
+    impl ControlSocket {
+        /// `None` if the socket is turned off or couldn't be made, which is logged
+        pub fn start(config: &ControlConfig, requests: Sender<Request>) -> Option<Self> {
+            let path = PathBuf::from(config.socket.as_ref()?);
+            // a socket left behind by a bouncer that didn't exit cleanly is replaced, one that still answers belongs to another bouncer
+            if path.exists() {
+                if UnixStream::connect(&path).is_ok() {
+                    error!("Another bouncer is already listening on {}, commands can't be sent to this one with `ctl`", path.display());
+                    return None;
+                }
+                let _ = fs::remove_file(&path);
+            }
+            let mode = config.mode().expect("checked when the config was loaded");
+            let listener = match bind(&path, mode) {
+                Ok(listener) => listener,
+                Err(io_err) => {
+                    error!("Failed to make the control socket {}: {}", path.display(), io_err);
+                    return None;
+                }
+            };
+
+            let stopped = Arc::new(Mutex::new(false));
+            let stopped_clone = Arc::clone(&stopped);
+            let listen_thread = thread::Builder::new()
+                .name("control_socket".to_string())
+                .spawn(move || {
+                    for stream in listener.incoming() {
+                        if *stopped_clone.lock().unwrap() {
+                            break;
+                        }
+                        match stream {
+                            Ok(stream) => {
+                                let requests = requests.clone();
+                                // a slow client only holds up its own command
+                                thread::spawn(move || answer(stream, requests));
+                            },
+                            Err(io_err) => error!("Error Accepting A Control Socket Connection {}", io_err),
+                        }
+                    }
+                })
+                .unwrap();
+            info!("Listening for commands on {}", path.display());
+            Some(Self { path, stopped, listen_thread: Some(listen_thread) })
+        }
+    }
+
+    /// Makes the socket inside a folder only we can get into and moves it to `path` once it has `mode`,
+    /// so nobody can connect to it in between while it still has the default permissions
+    fn bind(path: &Path, mode: u32) -> io::Result<UnixListener> {
+        let name = path.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "the socket path has no file name"))?;
+        let mut private = name.to_os_string();
+        private.push(format!(".{}.tmp", std::process::id()));
+        let private = path.with_file_name(private);
+        let _ = fs::remove_dir_all(&private);
+        DirBuilder::new().mode(0o700).create(&private)?;
+
+        let socket = private.join("socket");
+        let bound = UnixListener::bind(&socket)
+            .and_then(|listener| fs::set_permissions(&socket, Permissions::from_mode(mode)).map(|_| listener))
+            .and_then(|listener| fs::rename(&socket, path).map(|_| listener));
+        let _ = fs::remove_dir_all(&private);
+        bound
+    }
+
+    impl Drop for ControlSocket {
+        fn drop(&mut self) {
+            *self.stopped.lock().unwrap() = true;
+            // wake the listener so it sees it was stopped
+            let _ = UnixStream::connect(&self.path);
+            if let Some(listen_thread) = self.listen_thread.take() {
+                let _ = listen_thread.join();
+            }
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+
+    /// Reads one command from the client, waits for the control loop to handle it and writes back the reply
+    fn answer(stream: UnixStream, requests: Sender<Request>) {
+        let _ = stream.set_read_timeout(Some(TIMEOUT));
+        let mut line = String::new();
+        if BufReader::new(&stream).read_line(&mut line).is_err() || line.trim().is_empty() {
+            return;
+        }
+        let reply = input::ask(&requests, Input::parse_input(line.trim().to_string()), TIMEOUT);
+        let (status, text) = match reply {
+            Reply::Done(text) => (DONE, text),
+            Reply::Failed(text) => (FAILED, text),
+        };
+        let mut stream = stream;
+        let _ = stream.write_all(format!("{}\n{}", status, text).as_bytes());
+    }
+
+    pub fn answering(path: &Path) -> bool {
+        UnixStream::connect(path).is_ok()
+    }
+
+    pub fn send(path: &Path, command: &str) -> io::Result<Reply> {
+        let mut stream = UnixStream::connect(path)?;
+        stream.write_all(format!("{}\n", command).as_bytes())?;
+        stream.shutdown(Shutdown::Write)?;
+        let mut answer = String::new();
+        stream.read_to_string(&mut answer)?;
+        let (status, text) = answer.split_once('\n').unwrap_or((&answer, ""));
+        match status {
+            DONE => Ok(Reply::Done(text.to_string())),
+            FAILED => Ok(Reply::Failed(text.to_string())),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "the bouncer sent back something that isn't a reply")),
+        }
+    }
+}
+
+#[cfg(unix)]
+pub use unix::ControlSocket;
+
+/// The control socket needs unix sockets, so there is none on other platforms
+#[cfg(not(unix))]
+pub struct ControlSocket;
+
+#[cfg(not(unix))]
+impl ControlSocket {
+    pub fn start(config: &ControlConfig, _requests: std::sync::mpsc::Sender<crate::input::Request>) -> Option<Self> {
+        if config.socket.is_some() {
+            tracing::warn!("The control socket only works on unix, `ctl` can't be used");
+        }
+        None
+    }
+}
+
+/// whether a bouncer is listening on the control socket
+pub fn answering(path: &Path) -> bool {
+    #[cfg(unix)]
+    return unix::answering(path);
+    #[cfg(not(unix))]
+    return false;
+}
+
+/// Sends one command to a running bouncer and returns its reply
+pub fn send(path: &Path, command: &str) -> io::Result<Reply> {
+    #[cfg(unix)]
+    return unix::send(path, command);
+    #[cfg(not(unix))]
+    return Err(io::Error::new(io::ErrorKind::Unsupported, format!("the control socket {} only works on unix", path.display())));
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{
+        fs,
+        os::unix::fs::{FileTypeExt, PermissionsExt},
+        sync::{mpsc, Arc, Mutex},
+        thread,
+    };
+
+    use super::*;
+    use crate::input::{InputCode, Request};
+
+    #[test]
+    fn commands_round_trip_through_the_socket() {
+        let folder = std::env::temp_dir().join(format!("bouncer_control_test_{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        let path = folder.join("bouncer.sock");
+        let config = ControlConfig { socket: Some(path.to_string_lossy().to_string()), socket_mode: "640".to_string() };
+
+        // a stand-in for the control loop that refuses backups and remembers every command
+        let (requests, requests_rx) = mpsc::channel::<Request>();
+        let codes = Arc::new(Mutex::new(Vec::new()));
+        let codes_clone = Arc::clone(&codes);
+        thread::spawn(move || {
+            for request in requests_rx {
+                let reply = match request.code {
+                    InputCode::Backup => Reply::Failed("Error: A Backup Is Already Running".to_string()),
+                    _ => Reply::Done("Shutting down in 60 seconds\nsecond line".to_string()),
+                };
+                codes_clone.lock().unwrap().push(request.code.clone());
+                request.answer(reply);
+            }
+        });
+
+        let socket = ControlSocket::start(&config, requests).unwrap();
+        let meta = fs::metadata(&path).unwrap();
+        assert!(meta.file_type().is_socket());
+        assert_eq!(meta.permissions().mode() & 0o777, 0o640);
+        // nothing but the socket is left behind from making it
+        assert_eq!(fs::read_dir(&folder).unwrap().count(), 1);
+        assert!(answering(&path));
+        // a second bouncer can't take over the socket
+        assert!(ControlSocket::start(&config, mpsc::channel().0).is_none());
+
+        assert_eq!(send(&path, "stop -t 60").unwrap(), Reply::Done("Shutting down in 60 seconds\nsecond line".to_string()));
+        assert_eq!(send(&path, "backup").unwrap(), Reply::Failed("Error: A Backup Is Already Running".to_string()));
+        assert_eq!(*codes.lock().unwrap(), [InputCode::ExitWithTime(60), InputCode::Backup]);
+
+        drop(socket);
+        assert!(!path.exists());
+        assert!(!answering(&path));
+        assert!(send(&path, "status").is_err());
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
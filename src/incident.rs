@@ -0,0 +1,109 @@
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    fs::{self, create_dir_all},
+    io,
+    path::{Path, PathBuf},
+    process::ExitStatus,
+};
+
+use chrono::Local;
+use tracing::{info, warn};
+
+/// Everything we could find out about an unexpected server exit, saved to its own folder
+#[derive(Debug, Clone)]
+pub struct Incident {
+    pub folder: PathBuf,
+    pub status: String,
+    /// every file saved to `folder`, ready to be attached to a notification
+    pub files: Vec<PathBuf>,
+}
+
+/// the file names currently in `<server_folder>/crash-reports`, taken when the server starts so we can tell which reports are new
+pub fn crash_reports(server_folder: &Path) -> HashSet<OsString> {
+    match fs::read_dir(server_folder.join("crash-reports")) {
+        Ok(reports) => reports.flatten().map(|report| report.file_name()).collect(),
+        Err(_) => HashSet::new(),
+    }
+}
+
+/// Makes a folder called `name` inside `parent`, adding `_2`, `_3` and so on when one with that name is already there,
+/// so exits in the same second don't write over each other
+fn new_folder(parent: &Path, name: &str) -> io::Result<PathBuf> {
+    create_dir_all(parent)?;
+    let mut count = 1;
+    loop {
+        let folder = match count {
+            1 => parent.join(name),
+            _ => parent.join(format!("{}_{}", name, count)),
+        };
+        match fs::create_dir(&folder) {
+            Ok(()) => return Ok(folder),
+            Err(io_err) if io_err.kind() == io::ErrorKind::AlreadyExists => count += 1,
+            Err(io_err) => return Err(io_err),
+        }
+    }
+}
+
+/// Bundles the exit status, the tail of the server output and any crash report that isn't in `known_reports`
+/// into a new timestamped folder inside `incident_folder`
+pub fn collect(
+    incident_folder: &Path,
+    server_folder: &Path,
+    known_reports: &HashSet<OsString>,
+    status: &ExitStatus,
+    output: &[String],
+) -> io::Result<Incident> {
+    let folder = new_folder(incident_folder, &Local::now().format("%Y-%m-%d_%H-%M-%S").to_string())?;
+
+    let mut files = Vec::new();
+
+    let status_file = folder.join("exit_status.txt");
+    fs::write(&status_file, format!("{}\n", status))?;
+    files.push(status_file);
+
+    let output_file = folder.join("server_output.log");
+    fs::write(&output_file, output.iter().map(|line| format!("{}\n", line)).collect::<String>())?;
+    files.push(output_file);
+
+    // a server that dies before it gets going may never create the folder, that's not a problem
+    match fs::read_dir(server_folder.join("crash-reports")) {
+        Ok(reports) => {
+            for report in reports.flatten() {
+                if known_reports.contains(&report.file_name()) {
+                    continue;
+                }
+                let copy = folder.join(report.file_name());
+                match fs::copy(report.path(), &copy) {
+                    Ok(_) => files.push(copy),
+                    Err(io_err) => warn!("Failed To Copy Crash Report {}: {}", report.path().display(), io_err),
+                }
+            }
+        }
+        Err(io_err) if io_err.kind() == io::ErrorKind::NotFound => {}
+        Err(io_err) => warn!("Failed To Read Crash Reports In {}: {}", server_folder.display(), io_err),
+    }
+
+    info!("Saved Crash Incident To {}", folder.display());
+    Ok(Incident {
+        folder,
+        status: status.to_string(),
+        files,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folders_made_in_the_same_second_get_a_suffix() {
+        let parent = std::env::temp_dir().join(format!("bouncer_incident_test_{}", std::process::id()));
+        let folders = (0..3).map(|_| new_folder(&parent, "2024-06-01_04-00-00").unwrap()).collect::<Vec<_>>();
+        fs::remove_dir_all(&parent).unwrap();
+        assert_eq!(
+            folders,
+            [parent.join("2024-06-01_04-00-00"), parent.join("2024-06-01_04-00-00_2"), parent.join("2024-06-01_04-00-00_3")]
+        );
+    }
+}
@@ -1,13 +1,50 @@
-use std::{collections::HashSet, str::SplitWhitespace, sync::{mpsc::{self, Receiver}, Arc, Mutex}, thread::{self, JoinHandle}, time::Duration};
+use std::{collections::HashSet, str::SplitWhitespace, sync::{mpsc::{self, Receiver, Sender}, Arc, Mutex}, thread::{self, JoinHandle}, time::Duration};
 
-use tracing::info;
+use tracing::{info, warn};
 
 pub struct Input {
     check_input_thread: JoinHandle<()>,
-    input_rx: Receiver<String>,
+    input_tx: Sender<Request>,
+    input_rx: Receiver<Request>,
     killed: Arc<Mutex<bool>>,
 }
 
+/// A command typed on the console or sent from somewhere else, like the control socket
+pub struct Request {
+    pub code: InputCode,
+    /// gets the result of the command when whoever sent it is waiting for one
+    pub reply: Option<Sender<Reply>>,
+}
+
+/// Hands a command to the control loop and waits up to `timeout` for how it went
+pub fn ask(requests: &Sender<Request>, code: InputCode, timeout: Duration) -> Reply {
+    let (reply_tx, reply_rx) = mpsc::channel();
+    match requests.send(Request { code, reply: Some(reply_tx) }) {
+        Ok(()) => reply_rx.recv_timeout(timeout).unwrap_or_else(|_| Reply::Failed("The bouncer didn't answer in time".to_string())),
+        Err(_) => Reply::Failed("The bouncer is shutting down".to_string()),
+    }
+}
+
+impl Request {
+    /// Logs how the command went and passes it on to whoever sent it
+    pub fn answer(self, reply: Reply) {
+        match &reply {
+            Reply::Done(text) => text.lines().for_each(|line| info!("{}", line)),
+            Reply::Failed(text) => text.lines().for_each(|line| warn!("{}", line)),
+        }
+        if let Some(reply_tx) = self.reply {
+            let _ = reply_tx.send(reply);
+        }
+    }
+}
+
+/// How a command went
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reply {
+    Done(String),
+    Failed(String),
+}
+
 impl Input {
     pub fn new() -> Self {
         let (input_tx, input_rx) = mpsc::channel();
@@ -15,25 +52,26 @@ impl Input {
         let killed = Arc::new(Mutex::new(false));
 
         let killed_clone1 = Arc::clone(&killed);
+        let input_tx_clone = input_tx.clone();
 
         let check_input_thread = thread::Builder::new()
             .name("checkinput".to_string())
             .spawn(move || {
                 let killed = killed_clone1;
+                let input_tx = input_tx_clone;
                 loop {
                     if *killed.lock().unwrap() {
                         info!("[thread:checkinput]: Closing Thread");
                         break;
                     }
                     let mut input = String::new();
-                    std::io::stdin().read_line(&mut input).unwrap();
-                    input = input.trim().to_string();
-                    if input_tx.send(input.clone()).is_err() {
+                    // keep reading after `stop` so its countdown can still be cancelled, until stdin is closed
+                    if std::io::stdin().read_line(&mut input).unwrap() == 0 {
+                        info!("Closing [thread:checkinput]");
                         break;
                     }
-                    if input == *"stop" {
-                        info!("Closing [thread:checkinput]");
-                        drop(input_tx);
+                    input = input.trim().to_string();
+                    if input_tx.send(Request { code: Input::parse_input(input), reply: None }).is_err() {
                         break;
                     }
                 }
@@ -42,25 +80,26 @@ impl Input {
 
         Self {
             check_input_thread,
+            input_tx,
             input_rx,
             killed,
         }
     }
 
-    pub fn new_input(&mut self) -> Option<String> {
-        match self.input_rx.recv_timeout(Duration::from_millis(10)) {
-            Ok(input) => {
-                return Some(input);
-            }
-            Err(_) => {
-                return None;
-            }
-        }
+    /// Where other sources of commands send them, so they are handled along with the console
+    pub fn requests(&self) -> Sender<Request> {
+        self.input_tx.clone()
+    }
+
+    /// Waits up to `timeout` for a command to be typed or sent
+    pub fn next_input(&mut self, timeout: Duration) -> Option<Request> {
+        // we hold a sender ourselves so the channel never disconnects
+        self.input_rx.recv_timeout(timeout).ok()
     }
 
     pub fn kill(self) {
         *self.killed.lock().unwrap() = true;
-        self.check_input_thread.join().unwrap();
+        drop(self.check_input_thread);
         info!("Threads Closed");
     }
 
@@ -110,7 +149,13 @@ impl Input {
                     if command != InputCommand::default() {
                         return default_twice_command_err;
                     }
-                    return InputCode::Exit;
+                    command = InputCommand::Stop;
+                }
+                "start" => {
+                    if command != InputCommand::default() {
+                        return default_twice_command_err;
+                    }
+                    return InputCode::Start;
                 }
                 "restart" => {
                     if command != InputCommand::default() {
@@ -124,6 +169,51 @@ impl Input {
                     }
                     return InputCode::Backup;
                 }
+                "restore" => {
+                    if command != InputCommand::default() {
+                        return default_twice_command_err;
+                    }
+                    return match parts.next() {
+                        Some("list") => match parts.next() {
+                            None => InputCode::RestoreList { json: false },
+                            Some("--json") => InputCode::RestoreList { json: true },
+                            Some(_) => InputCode::InvalidMsg("Error: Unknown Flag After `restore list` usage: restore list or restore list --json".to_string()),
+                        },
+                        Some(id) => InputCode::Restore(id.to_string()),
+                        None => InputCode::InvalidMsg("Error: You Need To Say Which Backup To Restore usage: restore list or restore 2024-05-01_00-55-00".to_string()),
+                    };
+                }
+                "reload" => {
+                    if command != InputCommand::default() {
+                        return default_twice_command_err;
+                    }
+                    return InputCode::Reload;
+                }
+                "cancel" => {
+                    if command != InputCommand::default() {
+                        return default_twice_command_err;
+                    }
+                    return InputCode::Cancel;
+                }
+                "postpone" => {
+                    if command != InputCommand::default() {
+                        return default_twice_command_err;
+                    }
+                    return match parts.next().and_then(Input::parse_duration) {
+                        Some(seconds) if seconds > 0 => InputCode::Postpone(seconds),
+                        _ => InputCode::InvalidMsg("Error: You Need To Say How Long To Postpone For usage: postpone 10m or postpone 1h30m or postpone 90".to_string()),
+                    };
+                }
+                "status" => {
+                    if command != InputCommand::default() {
+                        return default_twice_command_err;
+                    }
+                    return match parts.next() {
+                        None => InputCode::Status { json: false },
+                        Some("--json") => InputCode::Status { json: true },
+                        Some(_) => InputCode::InvalidMsg("Error: Unknown Flag After `status` usage: status or status --json".to_string()),
+                    };
+                }
                 "cmd" => {
                     if command != InputCommand::default() {
                         return default_twice_command_err;
@@ -140,37 +230,69 @@ impl Input {
             }
         }
 
-        if command == InputCommand::Restart {
-            let mut time = 0;
-            let mut message = String::new();
-            for flag in flags.into_iter() {
-                match flag {
-                    InputFlag::Msg(msg) => {
-                        if message.is_empty() {
-                            message = msg;
-                            continue;
-                        }
-                        return InputCode::InvalidMsg("Error: Too Many `-m` Flags usage: restart -m \"Restarting in 50 minutes...\" -t 3000".to_string());
+        let usage = match command {
+            InputCommand::Restart => "restart -m \"Restarting in 50 minutes...\" -t 3000",
+            InputCommand::Stop => "stop -m \"Shutting down for maintenance...\" -t 3000",
+            InputCommand::NoCommand => return InputCode::Invalid,
+        };
+        let mut time = 0;
+        let mut message = String::new();
+        for flag in flags.into_iter() {
+            match flag {
+                InputFlag::Msg(msg) => {
+                    if message.is_empty() {
+                        message = msg;
+                        continue;
                     }
-                    InputFlag::Time(t) => {
-                        if time == 0 && t > 0 {
-                            time = t;
-                            continue;
-                        }
-                        return InputCode::InvalidMsg("Error: Either Time Is 0 Or Too Many `-t` Flags usage: restart -m \"Restarting in 50 minutes\" -t 3000".to_string());
+                    return InputCode::InvalidMsg(format!("Error: Too Many `-m` Flags usage: {}", usage));
+                }
+                InputFlag::Time(t) => {
+                    if time == 0 && t > 0 {
+                        time = t;
+                        continue;
                     }
+                    return InputCode::InvalidMsg(format!("Error: Either Time Is 0 Or Too Many `-t` Flags usage: {}", usage));
                 }
             }
-            if time > 0 && !message.is_empty() {
-                return InputCode::RestartWithMsgTime(message, time);
-            } else if time > 0 {
-                return InputCode::RestartWithTime(time);
-            } else if !message.is_empty() {
-                return InputCode::RestartWithMsg(message);
+        }
+        match (command, time, message.is_empty()) {
+            (InputCommand::Restart, 0, true) => InputCode::Restart,
+            (InputCommand::Restart, 0, false) => InputCode::RestartWithMsg(message),
+            (InputCommand::Restart, time, true) => InputCode::RestartWithTime(time),
+            (InputCommand::Restart, time, false) => InputCode::RestartWithMsgTime(message, time),
+            (_, 0, true) => InputCode::Exit,
+            (_, 0, false) => InputCode::ExitWithMsg(message),
+            (_, time, true) => InputCode::ExitWithTime(time),
+            (_, time, false) => InputCode::ExitWithMsgTime(message, time),
+        }
+    }
+
+    /// Reads a duration like `90`, `10m` or `1h30m` in seconds
+    fn parse_duration(duration: &str) -> Option<u64> {
+        if let Ok(seconds) = duration.parse::<u64>() {
+            return Some(seconds);
+        }
+        let mut seconds = 0u64;
+        let mut number = String::new();
+        for c in duration.chars() {
+            if c.is_ascii_digit() {
+                number.push(c);
+                continue;
             }
-            return InputCode::Restart;
+            let unit = match c {
+                'h' => 3600,
+                'm' => 60,
+                's' => 1,
+                _ => return None,
+            };
+            seconds = seconds.checked_add(number.parse::<u64>().ok()?.checked_mul(unit)?)?;
+            number.clear();
         }
-        InputCode::Invalid
+        // a number without a unit at the end like `1h30`
+        if !number.is_empty() {
+            return None;
+        }
+        Some(seconds)
     }
 
     fn parse_msg(parts: &mut SplitWhitespace) -> Option<String> {
@@ -214,17 +336,28 @@ impl Input {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum InputCode {
     SendMsg(String),
     RestartWithMsg(String),
     RestartWithTime(u64),
     RestartWithMsgTime(String, u64),
     Restart,
+    ExitWithMsg(String),
+    ExitWithTime(u64),
+    ExitWithMsgTime(String, u64),
     Exit,
+    /// start a server that is down, like after giving up on a crash loop
+    Start,
     Invalid,
     InvalidMsg(String),
     Backup,
+    RestoreList { json: bool },
+    Restore(String),
+    Reload,
+    Cancel,
+    Postpone(u64),
+    Status { json: bool },
     Cmd(String),
 }
 
@@ -239,6 +372,61 @@ impl InputFlag {}
 #[derive(Default, PartialEq, Eq)]
 pub enum InputCommand {
     Restart,
+    Stop,
     #[default]
     NoCommand,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_with_and_without_units() {
+        assert_eq!(Input::parse_duration("90"), Some(90));
+        assert_eq!(Input::parse_duration("45s"), Some(45));
+        assert_eq!(Input::parse_duration("10m"), Some(600));
+        assert_eq!(Input::parse_duration("2h"), Some(7200));
+        assert_eq!(Input::parse_duration("1h30m"), Some(5400));
+        assert_eq!(Input::parse_duration("1h30m15s"), Some(5415));
+        // units can come in any order and repeat, they're just added up
+        assert_eq!(Input::parse_duration("30m1h"), Some(5400));
+    }
+
+    #[test]
+    fn bad_durations() {
+        assert_eq!(Input::parse_duration("1h30"), None);
+        assert_eq!(Input::parse_duration("m"), None);
+        assert_eq!(Input::parse_duration("10x"), None);
+        assert_eq!(Input::parse_duration("-5"), None);
+        assert_eq!(Input::parse_duration("1.5h"), None);
+        assert_eq!(Input::parse_duration("ten"), None);
+        assert_eq!(Input::parse_duration("99999999999999999999h"), None);
+    }
+
+    #[test]
+    fn restart_and_stop_take_the_same_flags() {
+        let parse = |input: &str| Input::parse_input(input.to_string());
+        // `parse_msg` leaves a space after every word of a message
+        assert_eq!(parse("restart"), InputCode::Restart);
+        assert_eq!(parse("restart -t 600"), InputCode::RestartWithTime(600));
+        assert_eq!(parse("restart -m \"Updating mods\""), InputCode::RestartWithMsg("Updating mods ".to_string()));
+        assert_eq!(parse("restart -m \"Updating mods\" -t 600"), InputCode::RestartWithMsgTime("Updating mods ".to_string(), 600));
+        assert_eq!(parse("stop"), InputCode::Exit);
+        assert_eq!(parse("stop -t 60"), InputCode::ExitWithTime(60));
+        assert_eq!(parse("stop -m \"Bye for now\""), InputCode::ExitWithMsg("Bye for now ".to_string()));
+        assert_eq!(parse("stop -t 60 -m \"Bye for now\""), InputCode::ExitWithMsgTime("Bye for now ".to_string(), 60));
+        for bad in ["stop -t 0", "stop -t soon", "stop -t", "stop -t 10 -t 20", "stop -m \"a\" -m \"b\"", "stop restart", "restart stop"] {
+            assert!(matches!(parse(bad), InputCode::InvalidMsg(_)), "{}", bad);
+        }
+        assert_eq!(parse("-t 60"), InputCode::Invalid);
+    }
+
+    #[test]
+    fn postpone_needs_a_duration_over_0() {
+        assert_eq!(Input::parse_input("postpone 1h30m".to_string()), InputCode::Postpone(5400));
+        assert!(matches!(Input::parse_input("postpone 0".to_string()), InputCode::InvalidMsg(_)));
+        assert!(matches!(Input::parse_input("postpone soon".to_string()), InputCode::InvalidMsg(_)));
+        assert!(matches!(Input::parse_input("postpone".to_string()), InputCode::InvalidMsg(_)));
+    }
+}
@@ -1,306 +1,854 @@
-use backup::start_backup;
-use chrono::Local;
-use config::Config;
-use input::Input;
-use lettre::{message::header::ContentType, transport::smtp::authentication::Credentials, Message, SmtpTransport, Transport};
+use api::Api;
+use backup::{format_bytes, start_backup, Archive, BackupError, BackupJob};
+use chrono::{DateTime, TimeDelta, Utc};
+use clap::Parser;
+use cli::{Cli, Command};
+use config::{format_remaining, BackupMode, Config, ConfigWatcher, RESTART_ONLY_FIELDS};
+use control::ControlSocket;
+use input::{Input, Reply, Request};
+use metrics::{Metrics, RestartReason};
+use notify::{Notification, Notifiers, NotifyEvent};
+use output::{OutputHistory, ServerEvent};
 use process::Process;
-use rusty_time::Timer;
+use schedule::{Action, Countdown, Scheduler, Shutdown, OFFLINE_BACKUP_WARNING};
+use status::{BackupRecord, Planned, State, Status};
 use std::{
-    fs::remove_file, thread, time::{Duration, Instant}
+    collections::HashSet, path::Path, process::ExitCode, sync::{Arc, Mutex}, thread, time::Duration
 };
-use tracing::{error, info, warn, Level};
+use tracing::{error, info, warn};
 use tracing_subscriber::FmtSubscriber;
 
+mod api;
 mod backup;
+mod cli;
 mod config;
+mod control;
+mod incident;
 mod input;
+mod metrics;
+mod notify;
+mod output;
 mod process;
+mod rcon;
+mod restore;
+mod retention;
+mod schedule;
+mod signals;
+mod status;
+mod target;
+
+/// the longest the control loop waits before checking on the server and the config file
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
 
 #[derive(Debug, Default, PartialEq, Eq)]
 pub enum AppState {
-    RestartWithTime(u64),
-    Exit,
-    Backup,
+    /// stop the server and roll it back to the backup with this id
+    Restore(String),
     #[default]
     Normal,
 }
 
-fn main() {
+fn main() -> ExitCode {
     dotenv::dotenv().ok();
-    let (gmail, pass) = (dotenv::var("EMAILER_EMAIL").expect("EMAILER_EMAIL envar should exist"), dotenv::var("EMAILER_PASS").expect("EMAILER_PASS envar should exist"));
+    let cli = Cli::parse();
 
     // a builder for `FmtSubscriber`.
     let subscriber = FmtSubscriber::builder()
-        // all spans/events with a level higher than TRACE (e.g, debug, info, warn, etc.)
+        // all spans/events at `--log-level` or more important (e.g, warn and error for warn)
         // will be written to stdout.
-        .with_max_level(Level::TRACE)
+        .with_max_level(cli.log_level)
         // completes the builder.
         .finish();
 
     tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
 
-    let config_data = Config::read("config/server_bounce_config.json");
-    let (hour, minute) = config_data.backup_time.split_at(3);
-    let (mut hour, minute) = (hour.to_string(), minute.to_string());
-    hour.remove(2);
+    // the only command that doesn't need a config to exist yet
+    if let Some(Command::PrintDefaultConfig) = cli.command {
+        println!("{}", serde_json::to_string_pretty(&Config::default()).expect("the config always serializes"));
+        return ExitCode::SUCCESS;
+    }
 
-    // let status = start_backup(&config_data.server_folder, &config_data.backup_file_name).unwrap();
-    // if status.code().unwrap() == 1 {
-    //     println!("yay");
-    // }
+    let config_data = match Config::load(&cli.config) {
+        Ok(config_data) => config_data,
+        Err(err) => {
+            error!("{}", err);
+            return ExitCode::FAILURE;
+        }
+    };
 
-    let mut instant = Instant::now();
+    match cli.command.unwrap_or(Command::Run) {
+        // `restore list` works the same as it does in the console
+        Command::Restore { id: Some(id) } if id == "list" => {
+            list_backups(&config_data).iter().for_each(|line| info!("{}", line));
+            ExitCode::SUCCESS
+        },
+        Command::Run if cli.dry_run => {
+            log_plan(&config_data);
+            ExitCode::SUCCESS
+        },
+        Command::Run => run(&cli.config, config_data),
+        Command::CheckConfig => {
+            info!("{} is valid", cli.config.display());
+            ExitCode::SUCCESS
+        },
+        Command::BackupNow if cli.dry_run => {
+            info!(
+                "Dry run: would archive {} into {} as {} then upload it to {}",
+                config_data.server_folder,
+                config_data.backup_folder,
+                config_data.backup_format.extension(),
+                describe_targets(&config_data)
+            );
+            ExitCode::SUCCESS
+        },
+        Command::BackupNow => {
+            if let Some(busy) = server_busy(&config_data) {
+                error!("{}, back it up with `ctl backup` or stop it first", busy);
+                return ExitCode::FAILURE;
+            }
+            let notifiers = Notifiers::new(&config_data.notify);
+            if report_backup(start_backup(&config_data), &notifiers) {ExitCode::SUCCESS} else {ExitCode::FAILURE}
+        },
+        Command::Restore { id: None } => {
+            list_backups(&config_data).iter().for_each(|line| info!("{}", line));
+            ExitCode::SUCCESS
+        },
+        Command::Restore { id: Some(id) } => match restore::find(&config_data, &id) {
+            None => {
+                error!("There is no backup with the id {}. Leave the id out to list them", id);
+                ExitCode::FAILURE
+            },
+            Some(backup) if cli.dry_run => {
+                let source = match &backup.local {
+                    Some(path) => path.display().to_string(),
+                    None => format!("{} downloaded from {}", backup.name, backup.targets.join(" or ")),
+                };
+                info!("Dry run: would move {} aside and extract {} in its place", config_data.server_folder, source);
+                ExitCode::SUCCESS
+            },
+            Some(_) => {
+                if let Some(busy) = server_busy(&config_data) {
+                    error!("{}, roll it back with `ctl restore {}` or stop it first", busy, id);
+                    return ExitCode::FAILURE;
+                }
+                match restore::restore(&config_data, &id) {
+                    Ok(restored) => {
+                        info!("Rolled {} back to {}. The old folder was kept at {}", config_data.server_folder, restored.backup.name, restored.safety_copy.display());
+                        ExitCode::SUCCESS
+                    },
+                    Err(err) => {
+                        error!("Failed to restore backup {}: {}", id, err);
+                        ExitCode::FAILURE
+                    },
+                }
+            },
+        },
+        Command::Ctl { command } => {
+            let Some(socket) = &config_data.control.socket else {
+                error!("The control socket is turned off in {}", cli.config.display());
+                return ExitCode::FAILURE;
+            };
+            // the shell took the quotes off anything with spaces in it, like a message
+            let command = command
+                .iter()
+                .map(|arg| if arg.contains(char::is_whitespace) { format!("\"{}\"", arg) } else { arg.clone() })
+                .collect::<Vec<_>>()
+                .join(" ");
+            if cli.dry_run {
+                info!("Dry run: would send `{}` to the bouncer listening on {}", command, socket);
+                return ExitCode::SUCCESS;
+            }
+            match control::send(Path::new(socket), &command) {
+                Ok(Reply::Done(text)) => {
+                    println!("{}", text);
+                    ExitCode::SUCCESS
+                },
+                Ok(Reply::Failed(text)) => {
+                    eprintln!("{}", text);
+                    ExitCode::FAILURE
+                },
+                Err(io_err) => {
+                    error!("Couldn't reach the bouncer on {}, is it running? {}", socket, io_err);
+                    ExitCode::FAILURE
+                },
+            }
+        },
+        Command::PrintDefaultConfig => unreachable!("handled before the config is loaded"),
+    }
+}
+
+/// Starts the server and manages it until `stop` is typed
+fn run(config_path: &Path, mut config_data: Config) -> ExitCode {
+    signals::install();
+    let notifiers = Notifiers::new(&config_data.notify);
+    let mut config_watcher = ConfigWatcher::new(config_path);
+    let mut scheduler = Scheduler::new(&config_data, Utc::now());
 
     let mut input = Input::new();
+    // what the server printed lately, kept across restarts
+    let output = Arc::new(Mutex::new(OutputHistory::new(config_data.incident_log_lines)));
+    // what `/metrics` reports, kept up to date by the control loop
+    let metrics = Arc::new(Mutex::new(Metrics::default()));
+    let mut control_socket = ControlSocket::start(&config_data.control, input.requests());
+    let mut api = Api::start(&config_data.api, input.requests(), Arc::clone(&output), Arc::clone(&metrics));
 
     let mut app_state = AppState::default();
 
     let mut child: Option<Process>;
 
+    // players currently online according to the server output
+    let mut players = HashSet::<String>::new();
+
+    // the backup that is running, if there is one
+    let mut backup_job: Option<BackupJob> = None;
+
+    // the last command typed or sent, handled on the next pass of the control loop
+    let mut typed: Option<Request> = None;
+
+    // for `status`
+    let mut crashes = 0u32;
+    let mut last_backup: Option<BackupRecord> = None;
+
+    // a stop, restore, backup or manual restart that was counting down when the server came back by itself
+    let mut carried: Option<Countdown> = None;
+
     // main loop for starting a new process and new timers
     'main: loop {
         // start the child process and grab the stdin and child process
-        child = Some(Process::new(
-            config_data.server_start_file.clone(),
-            config_data.server_folder.clone(),
-            config_data.java_args.clone(),
-            config_data.nogui,
-        ));
+        child = Some(Process::new(&config_data, &output));
         if let Some(child) = &mut child {
             app_state = AppState::default();
             'restart: loop {
-                // create an iterator over the config warning msgs
-                let mut warning_msgs = config_data.restart_warning_msgs.iter().enumerate();
-
                 info!("Restart loop started with app_state {:?}", app_state);
 
-                // create two timers one for the reset duration and the other for the warning messages
-                info!(
-                    "creating new warning timer for {} minutes",
-                    config_data
-                        .restart_warning_msgs
-                        .get(0)
-                        .expect("No Warning Msg Configs Found")
-                        .time
-                        / 60
-                );
-                let mut warning_timer = Timer::new(Duration::from_millis(
-                    config_data
-                        .restart_warning_msgs
-                        .get(0)
-                        .expect("No Warning Msg Configs Found")
-                        .time
-                        * 1000,
-                ));
-                info!(
-                    "creating new restart timer for {} minutes",
-                    config_data.restart_duration / 60
-                );
-                let mut reset_timer =
-                    Timer::new(Duration::from_millis(config_data.restart_duration * 1000));
-
-                // inner loop for checking timers
+                // when the countdown to the next restart started, so a reload can work out the restart time again
+                let countdown_started = Utc::now();
+
+                // when the server goes down next and why, along with the warnings said before it
+                let mut countdown = match carried.take() {
+                    Some(countdown) => {
+                        info!("Carrying on with the {} countdown", countdown.shutdown);
+                        countdown
+                    },
+                    None => {
+                        let countdown = Countdown::new(
+                            Shutdown::ScheduledRestart,
+                            scheduler.next_restart(countdown_started),
+                            config_data.warnings(),
+                            countdown_started,
+                        );
+                        log_next_restart(&scheduler, countdown.at);
+                        countdown
+                    },
+                };
+
+                // the countdown ran out while a backup still needs the server to stay as it is
+                let mut waiting_for_backup = false;
+
+                // inner loop, woken when something is typed or the next deadline is due
                 'timer: loop {
-                    if app_state != AppState::default() || child.is_stopped() {
+                    if app_state != AppState::default() {
                         break 'timer;
                     }
-                    // grab the current delta
-                    let delta = instant.elapsed();
-                    instant = Instant::now();
-
-                    let curr_hour = Local::now().format("%H").to_string();
-                    let curr_min = Local::now().format("%M").to_string().parse::<u8>().expect("Should be a valid u8");
-
-                    // println!("{} {}", curr_hour, hour);
-                    if curr_hour == hour {
-                        let min = match minute.parse::<u8>() {
-                            Ok(min) => min,
-                            Err(_) => panic!("backup_time config data is invalid. Use 24 hour time format."),
-                        };
-                        if min.abs_diff(curr_min) == 1 {
-                            child.say("Automatic server backup in 1 minute. Server will shutdown and may take ahwile to restart.".to_string());
-                            thread::sleep(Duration::from_secs(60));
-                            app_state = AppState::Backup;
-                            break 'restart;
+                    if signals::stop_requested() {
+                        info!("Got a signal to exit, stopping the server");
+                        break 'main;
+                    }
+                    for incident in child.take_incidents() {
+                        crashes += 1;
+                        notifiers.send(
+                            Notification::new(
+                                NotifyEvent::Crash,
+                                "Minecraft Server Crashed",
+                                format!(
+                                    "The server unexpectedly stopped with {}. The crash reports and the last of the server output are attached and saved in {}",
+                                    incident.status,
+                                    incident.folder.display()
+                                ),
+                            )
+                            .with_attachments(&incident.files),
+                        );
+                    }
+                    // the server was brought back after a crash so start the restart timers over, only the scheduled restart is planned again
+                    if child.take_crashed() {
+                        metrics.lock().unwrap().restarted(RestartReason::Crash);
+                        carried = Some(countdown).filter(|countdown| countdown.shutdown != Shutdown::ScheduledRestart);
+                        continue 'restart;
+                    }
+                    if child.take_gave_up() {
+                        error!("Minecraft server is stuck in a crash loop and will not be restarted automatically");
+                        notifiers.send(Notification::new(
+                            NotifyEvent::CrashLoop,
+                            "Minecraft Server Crash Loop",
+                            "The server crashed too many times in a row and is no longer being restarted. Please fix thnx".to_string(),
+                        ));
+                    }
+                    for action in scheduler.due(Utc::now()) {
+                        match action {
+                            // offline backups are started a minute early for this warning
+                            Action::Backup => match config_data.backup_mode {
+                                _ if backup_job.is_some() => warn!("Skipping the automatic backup, the last one is still running"),
+                                BackupMode::Offline => match backup_countdown(&countdown, &config_data) {
+                                    Ok(backup) => {
+                                        child.say("Automatic server backup in 1 minute. Server will shutdown and may take ahwile to restart.".to_string());
+                                        countdown = backup;
+                                    },
+                                    Err(pending) => warn!("Skipping the automatic backup, a {} is already counting down", pending),
+                                },
+                                BackupMode::Online => {
+                                    child.say("Automatic server backup starting. The server may lag for a bit.".to_string());
+                                    backup_job = Some(BackupJob::online(child, &config_data));
+                                },
+                            },
+                            Action::Command(cmd) => {
+                                info!("Running scheduled command {}", cmd);
+                                child.cmd(cmd);
+                            },
+                            Action::Broadcast(msg) => child.say(msg),
+                        }
+                    }
+
+                    // react to anything the server printed since the last pass
+                    while let Some(event) = child.new_event() {
+                        match event {
+                            ServerEvent::Started(took) => {
+                                players.clear();
+                                info!("Minecraft server finished starting in {:.1}s", took.as_secs_f32());
+                                notifiers.send(Notification::new(
+                                    NotifyEvent::ServerStarted,
+                                    "Minecraft Server Started",
+                                    format!("The server finished starting in {:.1} seconds", took.as_secs_f32()),
+                                ));
+                            },
+                            ServerEvent::PlayerJoined(name) => {
+                                players.insert(name);
+                                info!("{} players online", players.len());
+                            },
+                            ServerEvent::PlayerLeft(name) => {
+                                players.remove(&name);
+                                info!("{} players online", players.len());
+                            },
+                            ServerEvent::Lagging { behind_ms, ticks } => {
+                                warn!("Minecraft server is lagging {}ms ({} ticks) behind", behind_ms, ticks);
+                                metrics.lock().unwrap().lagged(ticks);
+                            },
+                            ServerEvent::Saved => {
+                                info!("Minecraft server saved the world");
+                                if let Some(backup) = &mut backup_job {
+                                    backup.saved();
+                                }
+                            },
+                            ServerEvent::Exception(name) => error!("Minecraft server reported {}", name),
                         }
                     }
 
-                    // update the timer with the delta
-                    reset_timer.tick(delta);
-                    warning_timer.tick(delta);
+                    if let Some(backup) = &mut backup_job {
+                        let result = backup.poll(child);
+                        let restarted = backup.take_restarted();
+                        if let Some(result) = result {
+                            metrics.lock().unwrap().backup_finished(&result, backup.took());
+                            backup_job = None;
+                            last_backup = Some(BackupRecord::new(&result));
+                            report_backup(result, &notifiers);
+                        }
+                        // the server is back from an offline backup so start the restart timers over, keeping anything asked for meanwhile
+                        if restarted {
+                            carried = Some(countdown).filter(|countdown| countdown.shutdown != Shutdown::ScheduledRestart);
+                            continue 'restart;
+                        }
+                    }
+
+                    let mut reload = config_watcher.changed();
 
                     // check for user input
-                    if let Some(new_input) = input.new_input() {
-                        match Input::parse_input(new_input) {
-                            input::InputCode::SendMsg(msg) => child.say(msg),
+                    if let Some(request) = typed.take() {
+                        let reply = match request.code.clone() {
+                            input::InputCode::SendMsg(msg) => {
+                                child.say(msg.clone());
+                                Reply::Done(format!("Said {}", msg.trim()))
+                            },
                             input::InputCode::RestartWithMsg(msg) => {
                                 child.say(msg);
-                                thread::sleep(Duration::from_secs(5));
-                                break 'timer;
+                                countdown = countdown_in(Shutdown::ManualRestart, 5, &config_data);
+                                Reply::Done("Restarting in 5 seconds".to_string())
                             },
                             input::InputCode::RestartWithTime(time) => {
-                                app_state = AppState::RestartWithTime(time);
-                                child.say(format!("Manual Restart In {}", time));
-                                break 'timer;
+                                child.say(format!("Manual restart in {}", format_remaining(time)));
+                                countdown = countdown_in(Shutdown::ManualRestart, time, &config_data);
+                                Reply::Done(format!("Restarting in {}", format_remaining(time)))
                             },
                             input::InputCode::RestartWithMsgTime(msg, time) => {
-                                app_state = AppState::RestartWithTime(time);
                                 child.say(msg);
-                                break 'timer;
+                                countdown = countdown_in(Shutdown::ManualRestart, time, &config_data);
+                                Reply::Done(format!("Restarting in {}", format_remaining(time)))
                             },
                             input::InputCode::Restart => {
                                 child.say("Manual restart in 30 seconds...".to_string());
-                                thread::sleep(Duration::from_secs(30));
-                                break 'timer;
+                                countdown = countdown_in(Shutdown::ManualRestart, 30, &config_data);
+                                Reply::Done("Restarting in 30 seconds".to_string())
+                            },
+                            input::InputCode::ExitWithMsg(msg) => {
+                                child.say(msg);
+                                countdown = countdown_in(Shutdown::Exit, 5, &config_data);
+                                Reply::Done("Shutting down in 5 seconds".to_string())
+                            },
+                            input::InputCode::ExitWithTime(time) => {
+                                child.say(format!("Manual server shutdown in {}", format_remaining(time)));
+                                countdown = countdown_in(Shutdown::Exit, time, &config_data);
+                                Reply::Done(format!("Shutting down in {}", format_remaining(time)))
+                            },
+                            input::InputCode::ExitWithMsgTime(msg, time) => {
+                                child.say(msg);
+                                countdown = countdown_in(Shutdown::Exit, time, &config_data);
+                                Reply::Done(format!("Shutting down in {}", format_remaining(time)))
                             },
                             input::InputCode::Exit => {
                                 child.say("Manual server shutdown in 30 seconds...".to_string());
-                                thread::sleep(Duration::from_secs(30));
-                                break 'main;
+                                countdown = countdown_in(Shutdown::Exit, 30, &config_data);
+                                Reply::Done("Shutting down in 30 seconds".to_string())
                             },
-                            input::InputCode::Invalid => warn!("Error: Invalid Command Input usage: restart -m \"Restarting In 10 Minutes...\" -t 600"),
-                            input::InputCode::InvalidMsg(msg) => warn!("{}", msg),
-                            input::InputCode::Backup => {
-                                child.say("Manual server backup in 1 minute. Server will shutdown and may take ahwile to restart.".to_string());
-                                thread::sleep(Duration::from_secs(60));
-                                app_state = AppState::Backup;
-                                break 'restart;
+                            input::InputCode::Start if child.running().is_some() => Reply::Failed("Error: The Server Is Already Running".to_string()),
+                            input::InputCode::Start if backup_job.as_ref().is_some_and(BackupJob::holds_server) => {
+                                Reply::Failed("Error: The Server Is Down For A Backup And Starts Again Once It's Archived".to_string())
                             },
-                            input::InputCode::Cmd(cmd) => child.cmd(cmd),
-                        }
+                            // a fresh start, which also stops waiting out the crash backoff and forgets a crash loop that was given up on
+                            input::InputCode::Start => {
+                                child.restart();
+                                Reply::Done("Starting the server".to_string())
+                            },
+                            input::InputCode::Invalid => Reply::Failed("Error: Invalid Command Input usage: restart -m \"Restarting In 10 Minutes...\" -t 600".to_string()),
+                            input::InputCode::InvalidMsg(msg) => Reply::Failed(msg),
+                            input::InputCode::Backup => match config_data.backup_mode {
+                                _ if backup_job.is_some() => Reply::Failed("Error: A Backup Is Already Running".to_string()),
+                                BackupMode::Offline => match backup_countdown(&countdown, &config_data) {
+                                    Ok(backup) => {
+                                        child.say("Manual server backup in 1 minute. Server will shutdown and may take ahwile to restart.".to_string());
+                                        countdown = backup;
+                                        Reply::Done("Backing up in 1 minute".to_string())
+                                    },
+                                    Err(pending) => Reply::Failed(format!("Error: A {} Is Already Counting Down, `cancel` It First", pending)),
+                                },
+                                BackupMode::Online => {
+                                    child.say("Manual server backup starting. The server may lag for a bit.".to_string());
+                                    backup_job = Some(BackupJob::online(child, &config_data));
+                                    Reply::Done("Backup started".to_string())
+                                },
+                            },
+                            input::InputCode::RestoreList { json: false } => Reply::Done(list_backups(&config_data).join("\n")),
+                            input::InputCode::RestoreList { json: true } => {
+                                Reply::Done(serde_json::to_string(&restore::list(&config_data)).expect("the backup list always serializes"))
+                            },
+                            input::InputCode::Restore(id) => match restore::find(&config_data, &id) {
+                                Some(backup) => {
+                                    child.say(format!("Rolling the server back to the backup from {} in 1 minute. Server will shutdown and may take ahwile to restart.", backup.created));
+                                    countdown = countdown_in(Shutdown::Restore(id), 60, &config_data);
+                                    Reply::Done(format!("Restoring {} in 1 minute", backup.name))
+                                },
+                                None => Reply::Failed(format!("Error: There Is No Backup With The Id {} usage: restore list", id)),
+                            },
+                            input::InputCode::Reload => {
+                                reload = true;
+                                Reply::Done("Reloading the config".to_string())
+                            },
+                            input::InputCode::Cancel => match (countdown.shutdown.clone(), countdown.at) {
+                                (_, None) => Reply::Failed("Error: There Is Nothing To Cancel".to_string()),
+                                // skip this restart and count down to the one after it
+                                (Shutdown::ScheduledRestart, Some(at)) => {
+                                    child.say("The scheduled restart was cancelled".to_string());
+                                    countdown.move_to(scheduler.next_restart(at), Utc::now());
+                                    waiting_for_backup = false;
+                                    log_next_restart(&scheduler, countdown.at);
+                                    Reply::Done("Cancelled the scheduled restart".to_string())
+                                },
+                                (shutdown, Some(_)) => {
+                                    child.say(format!("The {} was cancelled", shutdown));
+                                    countdown = Countdown::new(Shutdown::ScheduledRestart, scheduler.next_restart(countdown_started), config_data.warnings(), Utc::now());
+                                    waiting_for_backup = false;
+                                    log_next_restart(&scheduler, countdown.at);
+                                    Reply::Done(format!("Cancelled the {}", shutdown))
+                                },
+                            },
+                            input::InputCode::Postpone(seconds) => match countdown.at {
+                                Some(at) => {
+                                    let now = Utc::now();
+                                    countdown.move_to(Some(at + TimeDelta::seconds(seconds as i64)), now);
+                                    waiting_for_backup = false;
+                                    let at = countdown.at.expect("Was just set");
+                                    child.say(format!(
+                                        "The {} was postponed by {}, it is now in {}",
+                                        countdown.shutdown,
+                                        format_remaining(seconds),
+                                        format_remaining((at - now).num_seconds().max(0) as u64)
+                                    ));
+                                    Reply::Done(format!("Postponed the {} to {}", countdown.shutdown, scheduler.zone().format(at)))
+                                },
+                                None => Reply::Failed("Error: There Is Nothing To Postpone".to_string()),
+                            },
+                            input::InputCode::Status { json } => {
+                                let now = Utc::now();
+                                // RCON knows who is online even if the server output was missed
+                                let players = match child.rcon("list").ok().as_deref().and_then(rcon::parse_list) {
+                                    Some(players) => players,
+                                    None => {
+                                        let mut players = players.iter().cloned().collect::<Vec<_>>();
+                                        players.sort();
+                                        players
+                                    },
+                                };
+                                let running = child.running();
+                                let status = Status {
+                                    state: State::new(&countdown.shutdown, backup_job.is_some()),
+                                    pid: running.as_ref().map(|running| running.pid),
+                                    server_started: running.as_ref().map(|running| running.since),
+                                    uptime_secs: running.as_ref().map(|running| (now - running.since).num_seconds().max(0) as u64),
+                                    countdown: countdown.at.map(|at| Planned::new(countdown.shutdown.to_string(), at, now)),
+                                    next_backup: scheduler
+                                        .upcoming()
+                                        .find_map(|(action, next)| next.filter(|_| *action == Action::Backup))
+                                        .map(|at| Planned::new("backup".to_string(), at, now)),
+                                    backup_running: backup_job.is_some(),
+                                    last_backup: last_backup.clone(),
+                                    crashes,
+                                    players,
+                                    memory_bytes: running.and_then(|running| status::memory(running.pid)),
+                                };
+                                match json {
+                                    true => Reply::Done(serde_json::to_string(&status).expect("the status always serializes")),
+                                    false => Reply::Done(status.lines(scheduler.zone()).join("\n")),
+                                }
+                            },
+                            input::InputCode::Cmd(cmd) => match child.send(&cmd) {
+                                Some(reply) => Reply::Done(reply.trim_end().to_string()),
+                                None => Reply::Done(format!("Sent {}", cmd.trim())),
+                            },
+                        };
+                        request.answer(reply);
                     }
 
-                    // check if we are ready to send a warning message
-                    if warning_timer.finished() {
-                        // grab the next warning message from the iterator
-                        if let Some(current_msg) = warning_msgs.next() {
-                            let (i, current_msg) = current_msg;
-                            info!("sending /say {}", current_msg.msg);
-
-                            // write the timed msg to the child stdin
-                            child.say(current_msg.msg.to_string());
-
-                            // set the new duration to the next time instead of the current one
-                            if let Some(new_durration) = config_data.restart_warning_msgs.get(i + 1)
-                            {
-                                info!("new timer duration {} minutes", new_durration.time / 60);
-                                warning_timer.set_duration(Duration::from_secs(new_durration.time));
-                            } else {
-                                info!("end of new timers");
+                    if reload {
+                        if let Some((new_config, changed)) = reload_config(config_path, &config_data) {
+                            config_data = new_config;
+                            child.reconfigure(&config_data);
+                            if changed.iter().any(|field| field == "notify") {
+                                notifiers.reconfigure(&config_data.notify);
+                            }
+                            if changed.iter().any(|field| field == "control") {
+                                // the old socket has to be gone before the new one can take its place
+                                drop(control_socket.take());
+                                control_socket = ControlSocket::start(&config_data.control, input.requests());
+                            }
+                            if changed.iter().any(|field| field == "api") {
+                                // free the address before listening on it again
+                                drop(api.take());
+                                api = Api::start(&config_data.api, input.requests(), Arc::clone(&output), Arc::clone(&metrics));
+                            }
+                            // carry on with the countdown that is already running using the new times
+                            let schedule_fields = ["schedule", "restart_duration", "restart_warnings", "restart_warning_msgs", "backup_time", "backup_mode"];
+                            if changed.iter().any(|field| schedule_fields.contains(&field.as_str())) {
+                                scheduler = Scheduler::new(&config_data, Utc::now());
+                                // anything other than the scheduled restart keeps its own time, as does one that was cancelled or postponed
+                                let at = match countdown.shutdown {
+                                    Shutdown::ScheduledRestart if !countdown.moved => scheduler.next_restart(countdown_started),
+                                    _ => countdown.at,
+                                };
+                                countdown.plan(at, config_data.warnings(), Utc::now());
+                                log_next_restart(&scheduler, countdown.at);
                             }
-                            // reset the timer after the new duration is set
-                            warning_timer.reset();
                         }
                     }
-                    // check if the reset timer is ready
-                    if reset_timer.finished() {
-                        info!("restart timer ready");
-                        break 'timer;
+
+                    let now = Utc::now();
+                    // check if we are ready to send a warning message
+                    if let Some(warning) = countdown.warning(now) {
+                        info!("sending /say {}", warning.msg);
+
+                        // write the timed msg to the child stdin
+                        child.say(warning.msg.clone());
                     }
-                    // sleep the current thread. We don't need to check as fast as we can. The implemenation can afford a slow check
-                    thread::sleep(Duration::from_secs(1));
-                }
-                // when we enter a manual restart with a timer
-                if let AppState::RestartWithTime(time) = app_state {
-                    info!("creating new restart timer for {} minutes", time / 60);
-                    let mut custom_timer = Timer::new(Duration::from_millis(time * 1000));
-                    'customrestart: loop {
-                        let delta = instant.elapsed();
-                        custom_timer.tick(delta);
-                        instant = Instant::now();
-
-                        if custom_timer.finished() {
-                            break 'customrestart;
+                    // check if it's time for the server to go down
+                    if countdown.finished(now) && backup_job.as_ref().is_some_and(BackupJob::holds_server) {
+                        if !waiting_for_backup {
+                            info!("Waiting for the backup to finish archiving before the server goes down");
+                            waiting_for_backup = true;
                         }
-                        // sleep the current thread. We don't need to check as fast as we can. The implemenation can afford a slow check
-                        thread::sleep(Duration::from_secs(1));
+                    } else if countdown.finished(now) {
+                        info!("Countdown to {:?} finished", countdown.shutdown);
+                        match countdown.shutdown.clone() {
+                            Shutdown::ScheduledRestart => {
+                                metrics.lock().unwrap().restarted(RestartReason::Scheduled);
+                                notifiers.send(Notification::new(
+                                    NotifyEvent::ScheduledRestart,
+                                    "Minecraft Server Restarting",
+                                    format!("The server is restarting on schedule at {}", scheduler.zone().format(now)),
+                                ));
+                            },
+                            Shutdown::ManualRestart => metrics.lock().unwrap().restarted(RestartReason::Manual),
+                            Shutdown::Backup if backup_job.is_some() => {
+                                warn!("Skipping the offline backup, the last one is still running");
+                                countdown = Countdown::new(Shutdown::ScheduledRestart, scheduler.next_restart(countdown_started), config_data.warnings(), now);
+                                log_next_restart(&scheduler, countdown.at);
+                                continue 'timer;
+                            },
+                            // the backup stops the server and starts it again once the archive is made, without holding up the control loop
+                            Shutdown::Backup => {
+                                metrics.lock().unwrap().restarted(RestartReason::Backup);
+                                backup_job = Some(BackupJob::offline(child, &config_data));
+                                // the restart timers start over once the server is back
+                                countdown = Countdown::new(Shutdown::ScheduledRestart, None, Vec::new(), now);
+                                continue 'timer;
+                            },
+                            Shutdown::Restore(id) => {
+                                metrics.lock().unwrap().restarted(RestartReason::Restore);
+                                app_state = AppState::Restore(id);
+                                break 'restart;
+                            },
+                            Shutdown::Exit => break 'main,
+                        }
+                        break 'timer;
                     }
+                    {
+                        let mut metrics = metrics.lock().unwrap();
+                        metrics.running = child.running();
+                        metrics.players = players.len();
+                    }
+                    // wait for something to be typed until the next deadline. Anything typed is kept if the server crashes in the meantime
+                    let deadlines = [scheduler.next_deadline(), countdown.next_deadline().filter(|_| !waiting_for_backup)];
+                    typed = input.next_input(wait_time(&deadlines));
                 }
                 // stop the current child process
-                if app_state == AppState::Exit {
-                    break 'main;
-                }
                 child.restart();
                 app_state = AppState::default();
             }
         }
         child.expect("Should be a child process").kill();
-        if app_state == AppState::Backup {
-            match start_backup(&config_data.server_folder, &config_data.backup_file_name) {
-                Ok(s) => {
-                    if s.success() {
-                        let curr_date = Local::now().format("%m.%d.%Y").to_string();
-                        match remove_file(format!(
-                            "./{} {}.zip",
-                            config_data.backup_file_name, curr_date
-                        )) {
-                            Ok(_) => info!("local server zip file deleted"),
-                            Err(e) => error!("failed to remove local server zip file {}", e),
-                        }
-                        info!(
-                            "Backup created and uploaded to Google Drive {}",
-                            s.to_string()
-                        );
-                    }
-                    else if s.code().unwrap() == 1 {
-                        error!(
-                            "Failed to create and upload backup to Google Drive"
-                        );
-                        let email = Message::builder()
-                            .from("Aaron Graybill <aarongraybill3@gmail.com>".parse().unwrap())
-                            .to("Aaron Graybill <aarongraybill3@gmail.com>".parse().unwrap())
-                            .subject("Minecraft Server Backup Issue")
-                            .header(ContentType::TEXT_PLAIN)
-                            .body(String::from("Please fix thnx"))
-                            .unwrap();
-        
-                        let creds = Credentials::new(gmail.clone(), pass.clone());
-        
-                        // Open a remote connection to gmail
-                        let mailer = SmtpTransport::relay("smtp.gmail.com")
-                            .unwrap()
-                            .credentials(creds)
-                            .build();
-        
-                        // Send the email
-                        match mailer.send(&email) {
-                            Ok(_) => println!("Email sent successfully!"),
-                            Err(e) => panic!("Could not send email: {e:?}"),
-                        }
-                    }
-                }
+        metrics.lock().unwrap().running = None;
+        if let AppState::Restore(id) = &app_state {
+            match restore::restore(&config_data, id) {
+                Ok(restored) => info!(
+                    "Rolled the server back to {}. The old server folder was kept at {}",
+                    restored.backup.name,
+                    restored.safety_copy.display()
+                ),
                 Err(err) => {
-                    error!(
-                        "Failed to create and upload backup to Google Drive: {}",
-                        err
-                    );
-                    let email = Message::builder()
-                        .from("Aaron Graybill <aarongraybill3@gmail.com>".parse().unwrap())
-                        .to("Aaron Graybill <aarongraybill3@gmail.com>".parse().unwrap())
-                        .subject("Minecraft Server Backup Issue")
-                        .header(ContentType::TEXT_PLAIN)
-                        .body(String::from("Please fix thnx"))
-                        .unwrap();
-
-                    let creds = Credentials::new("aarongraybill3@gmail.com".to_owned(), "xzbitnriwxdnhycf".to_owned());
-
-                    // Open a remote connection to gmail
-                    let mailer = SmtpTransport::relay("smtp.gmail.com")
-                        .unwrap()
-                        .credentials(creds)
-                        .build();
-
-                    // Send the email
-                    match mailer.send(&email) {
-                        Ok(_) => println!("Email sent successfully!"),
-                        Err(e) => panic!("Could not send email: {e:?}"),
-                    }
+                    error!("Failed to restore backup {}: {}", id, err);
+                    notifiers.send(Notification::new(NotifyEvent::RestoreFailed, "Minecraft Server Restore Issue", format!("Restoring the backup {} failed: {}\n\nPlease fix thnx", id, err)));
                 },
             }
         }
     }
     info!("Exiting App");
     child.expect("Should be a child").kill();
+    if let Some(result) = backup_job.and_then(BackupJob::finish) {
+        report_backup(result, &notifiers);
+    }
     input.kill();
+    drop(control_socket);
+    drop(api);
     thread::sleep(Duration::from_secs_f32(3.5));
+    ExitCode::SUCCESS
+}
+
+/// Why the server folder can't be touched from outside the bouncer, `None` if nothing is running in it
+fn server_busy(config: &Config) -> Option<String> {
+    if let Some(socket) = config.control.socket.as_ref().filter(|socket| control::answering(Path::new(socket))) {
+        return Some(format!("A bouncer is running and listening on {}", socket));
+    }
+    if status::world_locked(Path::new(&config.server_folder)) {
+        return Some(format!("A Minecraft server is running in {}", config.server_folder));
+    }
+    None
+}
+
+/// logs what `run` would do with this config, for `--dry-run`
+fn log_plan(config: &Config) {
+    let now = Utc::now();
+    let scheduler = Scheduler::new(config, now);
+    let at = |time: Option<DateTime<Utc>>| match time {
+        Some(time) => scheduler.zone().format(time),
+        None => "never".to_string(),
+    };
+    info!("Dry run: would start {} in {}", config.launch(), config.server_folder);
+    match &config.schedule.restart {
+        Some(_) => info!("Dry run: would restart at {} with {} warnings", at(scheduler.next_restart(now)), config.warnings().len()),
+        None => info!(
+            "Dry run: would restart every {} minutes with {} warnings",
+            config.restart_duration / 60,
+            config.warnings().len()
+        ),
+    }
+    for (action, next) in scheduler.upcoming() {
+        match action {
+            Action::Backup => info!(
+                "Dry run: would back up {} next at {} as {} into {} then upload it to {}",
+                match config.backup_mode {
+                    BackupMode::Offline => "offline",
+                    BackupMode::Online => "online",
+                },
+                at(next),
+                config.backup_format.extension(),
+                config.backup_folder,
+                describe_targets(config)
+            ),
+            _ => info!("Dry run: would {} next at {}", action, at(next)),
+        }
+    }
+}
+
+fn describe_targets(config: &Config) -> String {
+    if config.backup_targets.is_empty() {
+        return "no targets".to_string();
+    }
+    config.backup_targets.iter().map(|target| target.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+/// A countdown for the server to go down in `seconds`, with the configured warnings
+fn countdown_in(shutdown: Shutdown, seconds: u64, config: &Config) -> Countdown {
+    let now = Utc::now();
+    Countdown::new(shutdown, Some(now + TimeDelta::seconds(seconds as i64)), config.warnings(), now)
+}
+
+/// The countdown to an offline backup, which only takes the place of the scheduled restart.
+/// Anything else already counting down was asked for and is returned instead of being replaced
+fn backup_countdown(countdown: &Countdown, config: &Config) -> Result<Countdown, Shutdown> {
+    match &countdown.shutdown {
+        Shutdown::ScheduledRestart => Ok(countdown_in(Shutdown::Backup, OFFLINE_BACKUP_WARNING.as_secs(), config)),
+        pending => Err(pending.clone()),
+    }
+}
+
+/// How long the control loop can wait for something to be typed before one of the `deadlines` is due.
+/// Never more than `POLL_INTERVAL` so server output, crashes and config changes are still picked up
+fn wait_time(deadlines: &[Option<DateTime<Utc>>]) -> Duration {
+    let now = Utc::now();
+    deadlines
+        .iter()
+        .flatten()
+        .map(|deadline| (*deadline - now).to_std().unwrap_or(Duration::ZERO))
+        .fold(POLL_INTERVAL, Duration::min)
+}
+
+fn log_next_restart(scheduler: &Scheduler, restart_at: Option<DateTime<Utc>>) {
+    match restart_at {
+        Some(restart_at) => info!(
+            "Next restart at {} in {:.1} minutes",
+            scheduler.zone().format(restart_at),
+            (restart_at - Utc::now()).num_seconds() as f64 / 60.0
+        ),
+        None => warn!("The restart schedule never happens again, the server won't be restarted automatically"),
+    }
+}
+
+/// Loads the config again after it was saved or `reload` was typed, along with the fields that changed.
+/// A config with problems is logged and the current one is kept
+fn reload_config(config_path: &Path, config_data: &Config) -> Option<(Config, Vec<String>)> {
+    info!("Reloading {}", config_path.display());
+    let new_config = match Config::load(config_path) {
+        Ok(new_config) => new_config,
+        Err(err) => {
+            error!("{}", err);
+            error!("Keeping the current config until the problems are fixed");
+            return None;
+        }
+    };
+    let changed = config_data.changed_fields(&new_config);
+    if changed.is_empty() {
+        info!("Config reloaded with no changes");
+        return None;
+    }
+    for field in &changed {
+        if RESTART_ONLY_FIELDS.contains(&field.as_str()) {
+            warn!("`{}` changed and will take effect the next time the server restarts", field);
+        } else {
+            info!("`{}` changed and took effect right away", field);
+        }
+    }
+    Some((new_config, changed))
+}
+
+/// a line for every backup that can be restored with `restore <id>`
+fn list_backups(config: &Config) -> Vec<String> {
+    let backups = restore::list(config);
+    if backups.is_empty() {
+        return vec!["There are no backups to restore yet".to_string()];
+    }
+    let mut lines = vec![format!("{} backups, restore one with `restore <id>`", backups.len())];
+    for backup in backups {
+        let mut places = backup.targets.clone();
+        if backup.local.is_some() {
+            places.insert(0, "local".to_string());
+        }
+        lines.push(format!("{}  {} {}  {}", backup.id, format_bytes(backup.archive_bytes), backup.format.extension(), places.join(", ")));
+    }
+    lines
+}
+/// Logs a finished backup and notifies the server owner how it went.
+/// Returns false if the backup or any upload failed
+fn report_backup(result: Result<Archive, BackupError>, notifiers: &Notifiers) -> bool {
+    match result {
+        Ok(archive) => {
+            info!(
+                "Backup {} of {} files ({}) created ({})",
+                archive.path.display(),
+                archive.files,
+                format_bytes(archive.bytes),
+                format_bytes(archive.archive_bytes)
+            );
+            let failed = archive.uploads.iter().filter(|upload| upload.result.is_err()).collect::<Vec<_>>();
+            if failed.is_empty() {
+                info!("Backup uploaded to all {} targets", archive.uploads.len());
+                notifiers.send(Notification::new(
+                    NotifyEvent::BackupSucceeded,
+                    "Minecraft Server Backed Up",
+                    format!(
+                        "The backup {} of {} files ({}) was made ({}) and uploaded to {} targets",
+                        archive.path.display(),
+                        archive.files,
+                        format_bytes(archive.bytes),
+                        format_bytes(archive.archive_bytes),
+                        archive.uploads.len()
+                    ),
+                ));
+                return true;
+            }
+            let report = archive
+                .uploads
+                .iter()
+                .map(|upload| match &upload.result {
+                    Ok(_) => format!("{}: ok", upload.target),
+                    Err(err) => format!("{}: FAILED {}", upload.target, err),
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            error!("Backup failed to upload to {} of {} targets", failed.len(), archive.uploads.len());
+            notifiers.send(Notification::new(
+                NotifyEvent::BackupFailed,
+                "Minecraft Server Backup Issue",
+                format!("The backup {} was made but some uploads failed:\n{}\n\nPlease fix thnx", archive.path.display(), report),
+            ));
+            false
+        }
+        Err(err) => {
+            error!("Failed to create backup: {}", err);
+            notifiers.send(Notification::new(NotifyEvent::BackupFailed, "Minecraft Server Backup Issue", format!("The backup failed: {}\n\nPlease fix thnx", err)));
+            false
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offline_backups_only_replace_the_scheduled_restart() {
+        let config = Config::default();
+        let backup = backup_countdown(&countdown_in(Shutdown::ScheduledRestart, 3600, &config), &config).unwrap();
+        assert_eq!(backup.shutdown, Shutdown::Backup);
+        assert!(backup.at.is_some_and(|at| at <= Utc::now() + TimeDelta::seconds(OFFLINE_BACKUP_WARNING.as_secs() as i64)));
+
+        for pending in [Shutdown::ManualRestart, Shutdown::Exit, Shutdown::Restore("3".to_string()), Shutdown::Backup] {
+            let countdown = countdown_in(pending.clone(), 600, &config);
+            assert_eq!(backup_countdown(&countdown, &config).err(), Some(pending));
+        }
+    }
 }
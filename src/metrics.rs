@@ -0,0 +1,215 @@
+use std::{fmt::Write, time::Duration};
+
+use chrono::{DateTime, Utc};
+
+use crate::{backup::{Archive, BackupError}, process::Running, status};
+
+/// Why the server was restarted, a label on `minecraft_server_restarts_total`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestartReason {
+    Scheduled,
+    Manual,
+    Crash,
+    /// went down for an offline backup
+    Backup,
+    Restore,
+}
+
+impl RestartReason {
+    const ALL: [RestartReason; 5] = [RestartReason::Scheduled, RestartReason::Manual, RestartReason::Crash, RestartReason::Backup, RestartReason::Restore];
+
+    fn label(self) -> &'static str {
+        match self {
+            RestartReason::Scheduled => "scheduled",
+            RestartReason::Manual => "manual",
+            RestartReason::Crash => "crash",
+            RestartReason::Backup => "backup",
+            RestartReason::Restore => "restore",
+        }
+    }
+}
+
+/// What the control loop keeps track of for `/metrics`. The API reads it when scraped along with the server's memory and CPU
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// the server that is running now
+    pub running: Option<Running>,
+    pub players: usize,
+    restarts: [u64; RestartReason::ALL.len()],
+    backups_succeeded: u64,
+    backups_failed: u64,
+    last_success: Option<SuccessfulBackup>,
+    lag_warnings: u64,
+    lagged_ticks: u64,
+}
+
+#[derive(Debug)]
+struct SuccessfulBackup {
+    finished: DateTime<Utc>,
+    took: Duration,
+    archive_bytes: u64,
+}
+
+impl Metrics {
+    pub fn restarted(&mut self, reason: RestartReason) {
+        self.restarts[reason as usize] += 1;
+    }
+
+    /// a backup only succeeded if the archive was made and uploaded to every target, the same as in `status`
+    pub fn backup_finished(&mut self, result: &Result<Archive, BackupError>, took: Duration) {
+        match result {
+            Ok(archive) if archive.uploads.iter().all(|upload| upload.result.is_ok()) => {
+                self.backups_succeeded += 1;
+                self.last_success = Some(SuccessfulBackup { finished: Utc::now(), took, archive_bytes: archive.archive_bytes });
+            },
+            _ => self.backups_failed += 1,
+        }
+    }
+
+    /// the server printed `Can't keep up!`
+    pub fn lagged(&mut self, ticks: u64) {
+        self.lag_warnings += 1;
+        self.lagged_ticks += ticks;
+    }
+
+    /// Everything in the prometheus text format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let now = Utc::now();
+        let running = self.running.as_ref();
+
+        metric(&mut out, "minecraft_server_up", "gauge", "Whether the server process is running", &[("", running.is_some() as u64 as f64)]);
+        if let Some(running) = running {
+            let uptime = (now - running.since).num_milliseconds().max(0) as f64 / 1000.0;
+            metric(&mut out, "minecraft_server_uptime_seconds", "gauge", "How long the server has been running", &[("", uptime)]);
+            if let Some(bytes) = status::memory(running.pid) {
+                metric(&mut out, "minecraft_server_memory_rss_bytes", "gauge", "Resident memory of the server and everything it started", &[("", bytes as f64)]);
+            }
+            if let Some(seconds) = status::cpu_seconds(running.pid) {
+                metric(&mut out, "minecraft_server_cpu_seconds_total", "counter", "CPU time used by the server and everything it started", &[("", seconds)]);
+            }
+        }
+        let players = if running.is_some() { self.players } else { 0 };
+        metric(&mut out, "minecraft_players_online", "gauge", "Players online according to the server output", &[("", players as f64)]);
+
+        let restarts = RestartReason::ALL
+            .iter()
+            .map(|reason| (format!("reason=\"{}\"", reason.label()), self.restarts[*reason as usize] as f64))
+            .collect::<Vec<_>>();
+        let restarts = restarts.iter().map(|(labels, value)| (labels.as_str(), *value)).collect::<Vec<_>>();
+        metric(&mut out, "minecraft_server_restarts_total", "counter", "Times the server was restarted since the bouncer started", &restarts);
+
+        metric(&mut out, "minecraft_lag_warnings_total", "counter", "Times the server printed that it can't keep up", &[("", self.lag_warnings as f64)]);
+        metric(&mut out, "minecraft_lag_ticks_total", "counter", "Ticks the server said it fell behind by in its lag warnings", &[("", self.lagged_ticks as f64)]);
+
+        let backups = [("result=\"success\"", self.backups_succeeded as f64), ("result=\"failure\"", self.backups_failed as f64)];
+        metric(&mut out, "minecraft_backups_total", "counter", "Backups finished since the bouncer started", &backups);
+        if let Some(backup) = &self.last_success {
+            let finished = backup.finished.timestamp_millis() as f64 / 1000.0;
+            metric(&mut out, "minecraft_backup_last_success_timestamp_seconds", "gauge", "When the last successful backup finished", &[("", finished)]);
+            metric(&mut out, "minecraft_backup_last_duration_seconds", "gauge", "How long the last successful backup took", &[("", backup.took.as_secs_f64())]);
+            metric(&mut out, "minecraft_backup_last_size_bytes", "gauge", "Size of the archive of the last successful backup", &[("", backup.archive_bytes as f64)]);
+        }
+        out
+    }
+}
+
+/// Writes one metric with its help and type, followed by a sample for every set of labels
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, samples: &[(&str, f64)]) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    for (labels, value) in samples {
+        match labels.is_empty() {
+            true => { let _ = writeln!(out, "{} {}", name, value); },
+            false => { let _ = writeln!(out, "{}{{{}}} {}", name, labels, value); },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use chrono::TimeDelta;
+
+    use super::*;
+    use crate::{backup::Upload, target::TargetError};
+
+    /// the value of the sample written exactly like `sample`, like `minecraft_backups_total{result="success"}`
+    fn value(rendered: &str, sample: &str) -> Option<f64> {
+        rendered.lines().find_map(|line| line.strip_prefix(sample)?.strip_prefix(' ')?.parse().ok())
+    }
+
+    fn archive(upload: Result<(), TargetError>) -> Result<Archive, BackupError> {
+        Ok(Archive {
+            path: PathBuf::from("backups/server backup 2024-05-01_00-55-00.zip"),
+            files: 3,
+            bytes: 4096,
+            archive_bytes: 1024,
+            uploads: vec![Upload { target: "local folder mirror".to_string(), result: upload }],
+        })
+    }
+
+    #[test]
+    fn every_metric_has_help_and_a_type() {
+        let rendered = Metrics::default().render();
+        let mut lines = rendered.lines().peekable();
+        while let Some(line) = lines.next() {
+            let name = line.strip_prefix("# HELP ").and_then(|help| help.split(' ').next()).unwrap_or_else(|| panic!("expected HELP, got {}", line));
+            let kind = lines.next().and_then(|line| line.strip_prefix(&format!("# TYPE {} ", name))).unwrap();
+            assert!(["gauge", "counter"].contains(&kind), "{}", kind);
+            assert!(name.ends_with("_total") == (kind == "counter"), "{}", name);
+            // every sample follows its metric
+            while lines.peek().is_some_and(|line| !line.starts_with('#')) {
+                assert!(lines.next().unwrap().starts_with(name));
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_gauges_are_left_out() {
+        let rendered = Metrics::default().render();
+        assert_eq!(value(&rendered, "minecraft_server_up"), Some(0.0));
+        assert_eq!(value(&rendered, "minecraft_players_online"), Some(0.0));
+        for missing in ["minecraft_server_uptime_seconds", "minecraft_server_memory_rss_bytes", "minecraft_backup_last_success_timestamp_seconds", "minecraft_backup_last_size_bytes"] {
+            assert!(!rendered.contains(missing), "{}", missing);
+        }
+        for reason in RestartReason::ALL {
+            assert_eq!(value(&rendered, &format!("minecraft_server_restarts_total{{reason=\"{}\"}}", reason.label())), Some(0.0));
+        }
+    }
+
+    #[test]
+    fn counts_and_labels() {
+        let mut metrics = Metrics {
+            running: Some(Running { pid: std::process::id(), since: Utc::now() - TimeDelta::seconds(90), folder: PathBuf::from("server") }),
+            players: 2,
+            ..Metrics::default()
+        };
+        metrics.restarted(RestartReason::Crash);
+        metrics.restarted(RestartReason::Crash);
+        metrics.restarted(RestartReason::Backup);
+        metrics.backup_finished(&archive(Ok(())), Duration::from_millis(2500));
+        // a backup that failed to upload somewhere is a failure, and doesn't replace the last success
+        metrics.backup_finished(&archive(Err(TargetError::TooLarge(1024))), Duration::from_secs(9));
+        metrics.backup_finished(&Err(BackupError::SaveTimeout(Duration::from_secs(120))), Duration::from_secs(120));
+        metrics.lagged(40);
+        metrics.lagged(2);
+        let rendered = metrics.render();
+
+        assert_eq!(value(&rendered, "minecraft_server_up"), Some(1.0));
+        assert!(value(&rendered, "minecraft_server_uptime_seconds").is_some_and(|uptime| (90.0..100.0).contains(&uptime)));
+        assert_eq!(value(&rendered, "minecraft_players_online"), Some(2.0));
+        assert_eq!(value(&rendered, "minecraft_server_restarts_total{reason=\"crash\"}"), Some(2.0));
+        assert_eq!(value(&rendered, "minecraft_server_restarts_total{reason=\"backup\"}"), Some(1.0));
+        assert_eq!(value(&rendered, "minecraft_server_restarts_total{reason=\"scheduled\"}"), Some(0.0));
+        assert_eq!(value(&rendered, "minecraft_backups_total{result=\"success\"}"), Some(1.0));
+        assert_eq!(value(&rendered, "minecraft_backups_total{result=\"failure\"}"), Some(2.0));
+        assert_eq!(value(&rendered, "minecraft_backup_last_duration_seconds"), Some(2.5));
+        assert_eq!(value(&rendered, "minecraft_backup_last_size_bytes"), Some(1024.0));
+        assert_eq!(value(&rendered, "minecraft_lag_warnings_total"), Some(2.0));
+        assert_eq!(value(&rendered, "minecraft_lag_ticks_total"), Some(42.0));
+        #[cfg(target_os = "linux")]
+        assert!(value(&rendered, "minecraft_server_memory_rss_bytes").is_some_and(|bytes| bytes > 0.0));
+    }
+}
@@ -0,0 +1,753 @@
+use std::{
+    env,
+    error::Error,
+    fmt::{self, Display},
+    fs::read,
+    path::PathBuf,
+    sync::mpsc::{self, Sender},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use chrono::{SecondsFormat, Utc};
+use lettre::{
+    address::AddressError,
+    message::{header::ContentType, Attachment, Mailbox, MultiPart, SinglePart},
+    transport::smtp::{self, authentication::Credentials},
+    Message, SmtpTransport, Transport,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::{error, info, warn};
+
+use crate::config::Problems;
+
+/// how long a webhook gets to respond
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where alerts about the server are sent
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct NotifyConfig {
+    pub email: Option<EmailConfig>,
+    pub webhooks: Vec<WebhookConfig>,
+}
+
+impl NotifyConfig {
+    /// adds anything wrong with the notifiers to `problems`, `field` being where they are in the config
+    pub fn validate(&self, field: &str, problems: &mut Problems) {
+        if let Some(email) = &self.email {
+            if email.host.is_empty() {
+                problems.add(format!("{}.email.host", field), "can't be empty");
+            }
+            if let Err(err) = email.from.parse::<Mailbox>() {
+                problems.add(format!("{}.email.from", field), format!("{:?} isn't a valid address: {}", email.from, err));
+            }
+            if email.to.is_empty() {
+                problems.add(format!("{}.email.to", field), "needs at least one address");
+            }
+            for (i, to) in email.to.iter().enumerate() {
+                if let Err(err) = to.parse::<Mailbox>() {
+                    problems.add(format!("{}.email.to[{}]", field, i), format!("{:?} isn't a valid address: {}", to, err));
+                }
+            }
+        }
+        for (i, webhook) in self.webhooks.iter().enumerate() {
+            if !webhook.url.starts_with("http://") && !webhook.url.starts_with("https://") {
+                problems.add(format!("{}.webhooks[{}].url", field, i), "must start with http:// or https://");
+            }
+        }
+    }
+}
+
+/// The things a notifier can subscribe to
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NotifyEvent {
+    /// the server finished starting, including after restarts
+    ServerStarted,
+    /// the restart timer ran out and the server is being restarted
+    ScheduledRestart,
+    /// the server stopped without being asked to
+    Crash,
+    /// the server crashed too often and is no longer being restarted
+    CrashLoop,
+    /// a backup was made and uploaded to every target
+    BackupSucceeded,
+    /// a backup couldn't be made or failed to upload somewhere
+    BackupFailed,
+    /// rolling back to a backup failed
+    RestoreFailed,
+}
+
+impl NotifyEvent {
+    pub const ALL: [NotifyEvent; 7] = [
+        NotifyEvent::ServerStarted,
+        NotifyEvent::ScheduledRestart,
+        NotifyEvent::Crash,
+        NotifyEvent::CrashLoop,
+        NotifyEvent::BackupSucceeded,
+        NotifyEvent::BackupFailed,
+        NotifyEvent::RestoreFailed,
+    ];
+
+    /// the name used in the config and webhook payloads
+    pub fn name(&self) -> &'static str {
+        match self {
+            NotifyEvent::ServerStarted => "server_started",
+            NotifyEvent::ScheduledRestart => "scheduled_restart",
+            NotifyEvent::Crash => "crash",
+            NotifyEvent::CrashLoop => "crash_loop",
+            NotifyEvent::BackupSucceeded => "backup_succeeded",
+            NotifyEvent::BackupFailed => "backup_failed",
+            NotifyEvent::RestoreFailed => "restore_failed",
+        }
+    }
+
+    /// whether something went wrong that needs looking at
+    pub fn is_problem(&self) -> bool {
+        matches!(
+            self,
+            NotifyEvent::Crash | NotifyEvent::CrashLoop | NotifyEvent::BackupFailed | NotifyEvent::RestoreFailed
+        )
+    }
+}
+
+/// email only goes out when something needs fixing, like it always has
+fn default_email_events() -> Vec<NotifyEvent> {
+    NotifyEvent::ALL.into_iter().filter(NotifyEvent::is_problem).collect()
+}
+
+fn default_webhook_events() -> Vec<NotifyEvent> {
+    NotifyEvent::ALL.to_vec()
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TlsMode {
+    /// plain text, only meant for a local relay or test sink
+    None,
+    /// upgrade a plain connection with STARTTLS, usually on port 587
+    #[default]
+    Starttls,
+    /// TLS from the start, usually on port 465
+    Tls,
+}
+
+/// Emails alerts through an SMTP server
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EmailConfig {
+    pub host: String,
+    /// left out to use the usual port for `tls`
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub tls: TlsMode,
+    /// like `Minecraft Server <server@example.com>`
+    pub from: String,
+    pub to: Vec<String>,
+    /// the environment variable holding the SMTP username
+    #[serde(default = "default_username_env")]
+    pub username_env: String,
+    /// the environment variable holding the SMTP password
+    #[serde(default = "default_password_env")]
+    pub password_env: String,
+    #[serde(default = "default_email_events")]
+    pub events: Vec<NotifyEvent>,
+}
+
+fn default_username_env() -> String {
+    "EMAILER_EMAIL".to_string()
+}
+
+fn default_password_env() -> String {
+    "EMAILER_PASS".to_string()
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookKind {
+    /// a Discord channel webhook, sent as an embed
+    Discord,
+    /// a Slack incoming webhook, sent as blocks
+    Slack,
+    /// any other url, sent the payload documented in the README
+    Json,
+}
+
+/// Posts alerts to a chat channel or anything else that takes a webhook
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WebhookConfig {
+    #[serde(rename = "type")]
+    pub kind: WebhookKind,
+    pub url: String,
+    #[serde(default = "default_webhook_events")]
+    pub events: Vec<NotifyEvent>,
+}
+
+/// Something worth telling the server owner about
+pub struct Notification {
+    pub event: NotifyEvent,
+    pub subject: String,
+    pub body: String,
+    /// files like crash reports to send along, if the notifier supports it
+    pub attachments: Vec<PathBuf>,
+}
+
+impl Notification {
+    pub fn new(event: NotifyEvent, subject: &str, body: String) -> Self {
+        Self {
+            event,
+            subject: subject.to_string(),
+            body,
+            attachments: Vec::new(),
+        }
+    }
+
+    pub fn with_attachments(mut self, attachments: &[PathBuf]) -> Self {
+        self.attachments.extend_from_slice(attachments);
+        self
+    }
+}
+
+#[derive(Debug)]
+pub enum NotifyError {
+    /// a `from` or `to` address couldn't be parsed
+    Address(String, AddressError),
+    /// the email couldn't be put together
+    Email(lettre::error::Error),
+    Smtp(smtp::Error),
+    /// the webhook responded with an error, or couldn't be reached
+    Http(String),
+}
+
+impl Display for NotifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NotifyError::Address(address, err) => write!(f, "invalid email address {}: {}", address, err),
+            NotifyError::Email(err) => write!(f, "failed to build the email: {}", err),
+            NotifyError::Smtp(err) => write!(f, "smtp error: {}", err),
+            NotifyError::Http(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl Error for NotifyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            NotifyError::Address(_, err) => Some(err),
+            NotifyError::Email(err) => Some(err),
+            NotifyError::Smtp(err) => Some(err),
+            NotifyError::Http(_) => None,
+        }
+    }
+}
+
+/// A way of getting notifications to the server owner
+pub trait Notifier: Display + Send + Sync {
+    fn notify(&self, notification: &Notification) -> Result<(), NotifyError>;
+}
+
+/// every notifier along with the events it wants
+type Subscribed = Vec<(Box<dyn Notifier>, Vec<NotifyEvent>)>;
+
+/// What the notifier thread is asked to do, in order
+enum NotifyJob {
+    Send(Notification),
+    /// use these notifiers for everything sent from now on
+    Replace(Subscribed),
+}
+
+/// Every configured notifier along with the events it wants. Notifications are sent one after the other on their own thread
+/// so a slow mail server or webhook never holds up the control loop. A notifier failing is logged and never stops the others
+pub struct Notifiers {
+    jobs: Option<Sender<NotifyJob>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Notifiers {
+    /// sets up every notifier in the config, notifiers that can't be set up are logged and left out
+    pub fn new(config: &NotifyConfig) -> Self {
+        let (jobs, jobs_rx) = mpsc::channel::<NotifyJob>();
+        let thread = thread::Builder::new()
+            .name("notifier".to_string())
+            .spawn(move || {
+                let mut notifiers = Subscribed::new();
+                // ends once every notification has gone out and the sender is dropped
+                for job in jobs_rx {
+                    match job {
+                        NotifyJob::Send(notification) => send(&notifiers, &notification),
+                        NotifyJob::Replace(replacement) => notifiers = replacement,
+                    }
+                }
+            })
+            .unwrap();
+        let notifiers = Self { jobs: Some(jobs), thread: Some(thread) };
+        notifiers.reconfigure(config);
+        notifiers
+    }
+
+    /// Uses the notifiers in a reloaded config from now on. Notifications that are still waiting go out with the old ones
+    pub fn reconfigure(&self, config: &NotifyConfig) {
+        let mut notifiers = Subscribed::new();
+        if let Some(email) = &config.email {
+            match EmailNotifier::new(email) {
+                Ok(notifier) => notifiers.push((Box::new(notifier), email.events.clone())),
+                Err(err) => error!("Email notifications are disabled: {}", err),
+            }
+        }
+        for webhook in &config.webhooks {
+            notifiers.push((Box::new(WebhookNotifier::new(webhook)), webhook.events.clone()));
+        }
+        if notifiers.is_empty() {
+            warn!("No notifiers are configured, alerts will only be logged");
+        }
+        self.queue(NotifyJob::Replace(notifiers));
+    }
+
+    /// Sends the notification in the background
+    pub fn send(&self, notification: Notification) {
+        self.queue(NotifyJob::Send(notification));
+    }
+
+    fn queue(&self, job: NotifyJob) {
+        if let Some(jobs) = &self.jobs {
+            let _ = jobs.send(job);
+        }
+    }
+}
+
+impl Drop for Notifiers {
+    fn drop(&mut self) {
+        // let the notifications that are still waiting go out, so they aren't lost when the bouncer exits
+        drop(self.jobs.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn send(notifiers: &Subscribed, notification: &Notification) {
+    let subscribed = notifiers.iter().filter(|(_, events)| events.contains(&notification.event));
+    for (notifier, _) in subscribed {
+        match notifier.notify(notification) {
+            Ok(_) => info!("Sent \"{}\" with {}", notification.subject, notifier),
+            Err(err) => error!("Failed to send \"{}\" with {}: {}", notification.subject, notifier, err),
+        }
+    }
+}
+
+pub struct EmailNotifier {
+    mailer: SmtpTransport,
+    host: String,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+}
+
+impl EmailNotifier {
+    pub fn new(config: &EmailConfig) -> Result<Self, NotifyError> {
+        let parse = |address: &String| address.parse::<Mailbox>().map_err(|err| NotifyError::Address(address.clone(), err));
+        let from = parse(&config.from)?;
+        let to = config.to.iter().map(parse).collect::<Result<Vec<_>, _>>()?;
+
+        let mut builder = match config.tls {
+            TlsMode::None => SmtpTransport::builder_dangerous(&config.host),
+            TlsMode::Starttls => SmtpTransport::starttls_relay(&config.host).map_err(NotifyError::Smtp)?,
+            TlsMode::Tls => SmtpTransport::relay(&config.host).map_err(NotifyError::Smtp)?,
+        };
+        if let Some(port) = config.port {
+            builder = builder.port(port);
+        }
+        // no credentials means sending without logging in, which is fine for a local relay
+        match (env::var(&config.username_env), env::var(&config.password_env)) {
+            (Ok(username), Ok(password)) => builder = builder.credentials(Credentials::new(username, password)),
+            _ => warn!(
+                "{} or {} isn't set, emails will be sent without logging in to {}",
+                config.username_env, config.password_env, config.host
+            ),
+        }
+
+        Ok(Self {
+            mailer: builder.build(),
+            host: config.host.clone(),
+            from,
+            to,
+        })
+    }
+}
+
+impl Display for EmailNotifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "email via {}", self.host)
+    }
+}
+
+impl EmailNotifier {
+    /// the email for `notification`, with its attachments that could be read
+    fn message(&self, notification: &Notification) -> Result<Message, NotifyError> {
+        let mut parts = MultiPart::mixed().singlepart(SinglePart::plain(notification.body.clone()));
+        for path in &notification.attachments {
+            match read(path) {
+                Ok(content) => {
+                    let file_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+                    parts = parts.singlepart(Attachment::new(file_name).body(content, ContentType::TEXT_PLAIN));
+                }
+                Err(e) => error!("Could not attach {} to email: {}", path.display(), e),
+            }
+        }
+        let mut email = Message::builder().from(self.from.clone()).subject(&notification.subject);
+        for to in &self.to {
+            email = email.to(to.clone());
+        }
+        email.multipart(parts).map_err(NotifyError::Email)
+    }
+}
+
+impl Notifier for EmailNotifier {
+    fn notify(&self, notification: &Notification) -> Result<(), NotifyError> {
+        let email = self.message(notification)?;
+        self.mailer.send(&email).map_err(NotifyError::Smtp)?;
+        Ok(())
+    }
+}
+
+pub struct WebhookNotifier {
+    agent: ureq::Agent,
+    kind: WebhookKind,
+    url: String,
+}
+
+impl WebhookNotifier {
+    pub fn new(config: &WebhookConfig) -> Self {
+        Self {
+            agent: ureq::AgentBuilder::new().timeout(WEBHOOK_TIMEOUT).build(),
+            kind: config.kind,
+            url: config.url.clone(),
+        }
+    }
+
+    fn payload(&self, notification: &Notification) -> Value {
+        let timestamp = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+        match self.kind {
+            WebhookKind::Discord => json!({
+                "username": "Minecraft Server Bounce",
+                "embeds": [{
+                    "title": truncate(&notification.subject, 256),
+                    "description": truncate(&notification.body, 4096),
+                    // red for problems, green for everything else
+                    "color": if notification.event.is_problem() { 0xE74C3C } else { 0x2ECC71 },
+                    "footer": { "text": notification.event.name() },
+                    "timestamp": timestamp,
+                }],
+            }),
+            WebhookKind::Slack => json!({
+                "text": notification.subject,
+                "blocks": [
+                    { "type": "header", "text": { "type": "plain_text", "text": truncate(&notification.subject, 150) } },
+                    { "type": "section", "text": { "type": "mrkdwn", "text": truncate(&notification.body, 3000) } },
+                    { "type": "context", "elements": [{ "type": "mrkdwn", "text": format!("`{}` at {}", notification.event.name(), timestamp) }] },
+                ],
+            }),
+            WebhookKind::Json => json!({
+                "event": notification.event.name(),
+                "problem": notification.event.is_problem(),
+                "subject": notification.subject,
+                "body": notification.body,
+                "attachments": notification.attachments,
+                "timestamp": timestamp,
+            }),
+        }
+    }
+}
+
+impl Display for WebhookNotifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            WebhookKind::Discord => "discord",
+            WebhookKind::Slack => "slack",
+            WebhookKind::Json => "json",
+        };
+        // webhook urls carry their secret in the path so only the host is shown
+        let host = self.url.split("://").nth(1).unwrap_or(&self.url).split('/').next().unwrap_or_default();
+        write!(f, "{} webhook to {}", kind, host)
+    }
+}
+
+impl Notifier for WebhookNotifier {
+    fn notify(&self, notification: &Notification) -> Result<(), NotifyError> {
+        let response = self
+            .agent
+            .post(&self.url)
+            .set("Content-Type", "application/json")
+            .send_string(&self.payload(notification).to_string());
+        match response {
+            Ok(_) => Ok(()),
+            Err(ureq::Error::Status(code, response)) => {
+                let body = response.into_string().unwrap_or_default();
+                Err(NotifyError::Http(format!("webhook rejected with status {}: {}", code, body.trim())))
+            }
+            Err(err) => Err(NotifyError::Http(err.to_string())),
+        }
+    }
+}
+
+/// cuts `text` down to `max` characters for services with length limits
+fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+    let mut cut = text.chars().take(max - 1).collect::<String>();
+    cut.push('…');
+    cut
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        sync::{Arc, Mutex},
+    };
+
+    use super::*;
+
+    fn webhook(kind: &str, events: Option<&str>) -> WebhookConfig {
+        let events = events.map(|events| format!(r#", "events": {}"#, events)).unwrap_or_default();
+        serde_json::from_str(&format!(r#"{{"type": "{}", "url": "https://example.com/hook/secret"{}}}"#, kind, events)).unwrap()
+    }
+
+    fn crash() -> Notification {
+        Notification::new(NotifyEvent::Crash, "Minecraft Server Crashed", "exit status: 1".to_string())
+            .with_attachments(&[PathBuf::from("incidents/2024-05-01_00-55-00/server_output.log")])
+    }
+
+    /// the payload sent for `notification` without its timestamp, which changes every time
+    fn payload(kind: &str, notification: &Notification) -> (Value, String) {
+        let mut payload = WebhookNotifier::new(&webhook(kind, None)).payload(notification);
+        let timestamp = match kind {
+            "discord" => payload["embeds"][0]["timestamp"].take(),
+            "slack" => payload["blocks"][2]["elements"][0]["text"].take(),
+            _ => payload["timestamp"].take(),
+        };
+        (payload, timestamp.as_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn discord_payload() {
+        let (payload, timestamp) = payload("discord", &crash());
+        assert!(timestamp.ends_with('Z'));
+        assert_eq!(
+            payload,
+            json!({
+                "username": "Minecraft Server Bounce",
+                "embeds": [{
+                    "title": "Minecraft Server Crashed",
+                    "description": "exit status: 1",
+                    "color": 0xE74C3C,
+                    "footer": { "text": "crash" },
+                    "timestamp": null,
+                }],
+            })
+        );
+        let (succeeded, _) = self::payload("discord", &Notification::new(NotifyEvent::BackupSucceeded, "Backup", String::new()));
+        assert_eq!(succeeded["embeds"][0]["color"], 0x2ECC71);
+    }
+
+    #[test]
+    fn slack_payload() {
+        let (payload, context) = payload("slack", &crash());
+        assert!(context.starts_with("`crash` at "));
+        assert_eq!(
+            payload,
+            json!({
+                "text": "Minecraft Server Crashed",
+                "blocks": [
+                    { "type": "header", "text": { "type": "plain_text", "text": "Minecraft Server Crashed" } },
+                    { "type": "section", "text": { "type": "mrkdwn", "text": "exit status: 1" } },
+                    { "type": "context", "elements": [{ "type": "mrkdwn", "text": null }] },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn json_payload_matches_the_readme() {
+        let (payload, timestamp) = payload("json", &crash());
+        assert!(chrono::DateTime::parse_from_rfc3339(&timestamp).is_ok());
+        assert_eq!(
+            payload,
+            json!({
+                "event": "crash",
+                "problem": true,
+                "subject": "Minecraft Server Crashed",
+                "body": "exit status: 1",
+                "attachments": ["incidents/2024-05-01_00-55-00/server_output.log"],
+                "timestamp": null,
+            })
+        );
+    }
+
+    #[test]
+    fn long_text_is_cut_to_the_limits() {
+        let notification = Notification::new(NotifyEvent::Crash, &"s".repeat(300), "b".repeat(5000));
+        let (discord, _) = payload("discord", &notification);
+        assert_eq!(discord["embeds"][0]["title"].as_str().unwrap().chars().count(), 256);
+        assert_eq!(discord["embeds"][0]["description"].as_str().unwrap().chars().count(), 4096);
+        let (slack, _) = payload("slack", &notification);
+        assert_eq!(slack["blocks"][0]["text"]["text"].as_str().unwrap().chars().count(), 150);
+        assert_eq!(slack["blocks"][1]["text"]["text"].as_str().unwrap().chars().count(), 3000);
+    }
+
+    #[test]
+    fn truncate_counts_characters_not_bytes() {
+        assert_eq!(truncate("héllo wörld", 11), "héllo wörld");
+        assert_eq!(truncate("héllo wörld", 8), "héllo w…");
+        assert_eq!(truncate("é🎉é🎉é🎉", 3), "é🎉…");
+        assert_eq!(truncate("", 5), "");
+    }
+
+    /// A notifier that remembers the subject of everything sent to it
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl Display for Recorder {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "recorder")
+        }
+    }
+
+    impl Notifier for Recorder {
+        fn notify(&self, notification: &Notification) -> Result<(), NotifyError> {
+            self.0.lock().unwrap().push(notification.subject.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn notifiers_only_get_the_events_they_want() {
+        let (everything, crashes) = (Arc::new(Mutex::new(Vec::new())), Arc::new(Mutex::new(Vec::new())));
+        let notifiers: Subscribed = vec![
+            (Box::new(Recorder(Arc::clone(&everything))), webhook("json", None).events),
+            (Box::new(Recorder(Arc::clone(&crashes))), webhook("json", Some(r#"["crash", "crash_loop"]"#)).events),
+        ];
+        send(&notifiers, &crash());
+        send(&notifiers, &Notification::new(NotifyEvent::ServerStarted, "Started", String::new()));
+        send(&notifiers, &Notification::new(NotifyEvent::CrashLoop, "Crash Loop", String::new()));
+
+        assert_eq!(*everything.lock().unwrap(), ["Minecraft Server Crashed", "Started", "Crash Loop"]);
+        assert_eq!(*crashes.lock().unwrap(), ["Minecraft Server Crashed", "Crash Loop"]);
+    }
+
+    #[test]
+    fn webhooks_get_every_event_by_default() {
+        assert_eq!(webhook("discord", None).events, NotifyEvent::ALL);
+        assert_eq!(webhook("slack", Some("[]")).events, []);
+    }
+
+    /// an email config for `to` sending through `127.0.0.1:port` in plain text, without logging in
+    fn email(port: u16, to: &[&str]) -> EmailConfig {
+        serde_json::from_value(json!({
+            "host": "127.0.0.1",
+            "port": port,
+            "tls": "none",
+            "from": "Bouncer <bouncer@example.com>",
+            "to": to,
+            "username_env": "BOUNCER_TEST_UNSET_USERNAME",
+            "password_env": "BOUNCER_TEST_UNSET_PASSWORD",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn emails_go_to_every_address_with_attachments() {
+        let folder = std::env::temp_dir().join(format!("bouncer_notify_test_{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        fs::write(folder.join("server_output.log"), "[12:00:00] [Server thread/ERROR]: oh no\n").unwrap();
+        let notification = Notification::new(NotifyEvent::Crash, "Minecraft Server Crashed", "exit status: 1".to_string())
+            .with_attachments(&[folder.join("server_output.log"), folder.join("missing.log")]);
+
+        let notifier = EmailNotifier::new(&email(25, &["Admin <admin@example.com>", "ops@example.com"])).unwrap();
+        let email = String::from_utf8(notifier.message(&notification).unwrap().formatted()).unwrap();
+        fs::remove_dir_all(&folder).unwrap();
+
+        assert!(email.contains("From: Bouncer <bouncer@example.com>\r\n"), "{}", email);
+        assert!(email.contains("To: Admin <admin@example.com>, ops@example.com\r\n"), "{}", email);
+        assert!(email.contains("Subject: Minecraft Server Crashed\r\n"), "{}", email);
+        assert!(email.contains("exit status: 1"), "{}", email);
+        // the attachment that couldn't be read is left out instead of failing the email
+        assert!(email.contains("filename=\"server_output.log\""), "{}", email);
+        assert!(email.contains("oh no"), "{}", email);
+        assert!(!email.contains("missing.log"), "{}", email);
+        assert_eq!(notifier.to_string(), "email via 127.0.0.1");
+    }
+
+    #[test]
+    fn bad_addresses_are_refused() {
+        assert!(matches!(EmailNotifier::new(&email(25, &["not an address"])), Err(NotifyError::Address(address, _)) if address == "not an address"));
+    }
+
+    #[test]
+    fn email_only_gets_problems_by_default() {
+        assert_eq!(email(25, &["admin@example.com"]).events, [NotifyEvent::Crash, NotifyEvent::CrashLoop, NotifyEvent::BackupFailed, NotifyEvent::RestoreFailed]);
+    }
+
+    /// A plain text SMTP server that takes one email and returns what was said to it
+    fn smtp_sink() -> (u16, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let sink = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            let mut said = Vec::new();
+            writer.write_all(b"220 sink ready\r\n").unwrap();
+            let mut in_data = false;
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 0 {
+                let command = line.trim_end().to_string();
+                line.clear();
+                if in_data {
+                    // the transport keeps the connection open for the next email, so stop once this one is in
+                    if command == "." {
+                        writer.write_all(b"250 queued\r\n").unwrap();
+                        break;
+                    } else {
+                        said.push(command);
+                    }
+                    continue;
+                }
+                let reply: &[u8] = match command.split(' ').next().unwrap_or_default().to_ascii_uppercase().as_str() {
+                    "EHLO" => b"250 sink\r\n",
+                    "DATA" => {
+                        in_data = true;
+                        b"354 go ahead\r\n"
+                    },
+                    _ => b"250 ok\r\n",
+                };
+                said.push(command);
+                writer.write_all(reply).unwrap();
+            }
+            said
+        });
+        (port, sink)
+    }
+
+    #[test]
+    fn plain_text_smtp_for_a_local_relay() {
+        let (port, sink) = smtp_sink();
+        let notifier = EmailNotifier::new(&email(port, &["Admin <admin@example.com>"])).unwrap();
+        notifier.notify(&crash()).unwrap();
+
+        let said = sink.join().unwrap();
+        assert!(said.contains(&"MAIL FROM:<bouncer@example.com>".to_string()), "{:?}", said);
+        assert!(said.contains(&"RCPT TO:<admin@example.com>".to_string()), "{:?}", said);
+        assert!(said.contains(&"Subject: Minecraft Server Crashed".to_string()), "{:?}", said);
+        // nothing tried to log in or upgrade the connection
+        assert!(!said.iter().any(|line| line.starts_with("AUTH") || line.starts_with("STARTTLS")), "{:?}", said);
+    }
+
+    #[test]
+    fn webhook_names_hide_the_secret() {
+        assert_eq!(WebhookNotifier::new(&webhook("discord", None)).to_string(), "discord webhook to example.com");
+    }
+}
@@ -0,0 +1,220 @@
+use std::{collections::VecDeque, sync::OnceLock, time::Duration};
+
+use regex::Regex;
+
+/// Something the Minecraft server reported on its console that the bouncer may want to react to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerEvent {
+    /// the `Done (12.345s)! For help, type "help"` line printed once the world is loaded
+    Started(Duration),
+    PlayerJoined(String),
+    PlayerLeft(String),
+    /// `Can't keep up! Is the server overloaded? Running 2034ms or 40 ticks behind`
+    Lagging { behind_ms: u64, ticks: u64 },
+    /// the `Saved the game` confirmation printed after `save-all`
+    Saved,
+    /// a line naming a java exception or error, usually the head of a stack trace
+    Exception(String),
+}
+
+struct Patterns {
+    started: Regex,
+    joined: Regex,
+    left: Regex,
+    lagging: Regex,
+    saved: Regex,
+    exception: Regex,
+}
+
+fn patterns() -> &'static Patterns {
+    static PATTERNS: OnceLock<Patterns> = OnceLock::new();
+    PATTERNS.get_or_init(|| Patterns {
+        started: Regex::new(r#"^Done \((?P<secs>\d+(?:\.\d+)?)s\)!"#).unwrap(),
+        joined: Regex::new(r"^(?P<name>[A-Za-z0-9_]{1,16}) joined the game\s*$").unwrap(),
+        left: Regex::new(r"^(?P<name>[A-Za-z0-9_]{1,16}) left the game\s*$").unwrap(),
+        lagging: Regex::new(r"^Can't keep up!.*Running (?P<ms>\d+)ms or (?P<ticks>\d+) ticks behind").unwrap(),
+        saved: Regex::new(r"^Saved the game\s*$").unwrap(),
+        exception: Regex::new(r"(?P<name>(?:[A-Za-z_$][\w$]*\.)+[A-Za-z_$][\w$]*(?:Exception|Error))\b").unwrap(),
+    })
+}
+
+/// What was logged after a prefix like `[12:34:56] [Server thread/INFO]: `, `None` for a line without one like the rest of a stack trace.
+/// Player names can't hold `]` so the first `]: ` always ends the prefix, whatever a player wrote after it
+fn logged(line: &str) -> Option<&str> {
+    line.starts_with('[').then(|| line.split_once("]: ")).flatten().map(|(_, logged)| logged)
+}
+
+/// whether a player wrote it with chat, `/say` or `/me`, so it can look like anything
+fn said_by_player(logged: &str) -> bool {
+    logged.starts_with('<') || logged.starts_with('[') || logged.starts_with("* ")
+}
+
+/// Parses a single line of server output into a [`ServerEvent`].
+///
+/// Lines that don't mean anything to the bouncer return `None`
+pub fn parse_line(line: &str) -> Option<ServerEvent> {
+    let patterns = patterns();
+
+    let Some(logged) = logged(line) else {
+        // the lines after the first one of a stack trace have no prefix
+        return patterns.exception.captures(line).map(|caps| ServerEvent::Exception(caps["name"].to_string()));
+    };
+    if said_by_player(logged) {
+        return None;
+    }
+    if let Some(caps) = patterns.started.captures(logged) {
+        let secs = caps["secs"].parse::<f64>().unwrap_or_default();
+        return Some(ServerEvent::Started(Duration::from_secs_f64(secs)));
+    }
+    if let Some(caps) = patterns.joined.captures(logged) {
+        return Some(ServerEvent::PlayerJoined(caps["name"].to_string()));
+    }
+    if let Some(caps) = patterns.left.captures(logged) {
+        return Some(ServerEvent::PlayerLeft(caps["name"].to_string()));
+    }
+    if let Some(caps) = patterns.lagging.captures(logged) {
+        return Some(ServerEvent::Lagging {
+            behind_ms: caps["ms"].parse().unwrap_or_default(),
+            ticks: caps["ticks"].parse().unwrap_or_default(),
+        });
+    }
+    if patterns.saved.is_match(logged) {
+        return Some(ServerEvent::Saved);
+    }
+    if let Some(caps) = patterns.exception.captures(logged) {
+        return Some(ServerEvent::Exception(caps["name"].to_string()));
+    }
+    None
+}
+
+/// The most recent lines the server printed, oldest first
+pub struct OutputHistory {
+    lines: VecDeque<String>,
+    capacity: usize,
+    /// how many lines have ever been pushed, so a reader can ask for just the ones it hasn't seen
+    pushed: u64,
+}
+
+impl OutputHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            lines: VecDeque::with_capacity(capacity),
+            capacity,
+            pushed: 0,
+        }
+    }
+
+    pub fn push(&mut self, line: String) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.lines.len() == self.capacity {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line);
+        self.pushed += 1;
+    }
+
+    /// keeps `capacity` lines from now on, dropping the oldest if there are already more
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.lines.len() > capacity {
+            self.lines.pop_front();
+        }
+    }
+
+    /// the last `n` lines, oldest first
+    pub fn tail(&self, n: usize) -> Vec<String> {
+        self.lines.iter().skip(self.lines.len().saturating_sub(n)).cloned().collect()
+    }
+
+    /// The lines pushed after the first `seen`, oldest first, along with the count to pass as `seen` next time.
+    /// Lines that were already dropped to stay within the capacity are missed
+    pub fn since(&self, seen: u64) -> (Vec<String>, u64) {
+        let new = self.pushed.saturating_sub(seen).min(self.lines.len() as u64) as usize;
+        (self.tail(new), self.pushed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn started() {
+        assert_eq!(
+            parse_line(r#"[12:00:00] [Server thread/INFO]: Done (12.345s)! For help, type "help""#),
+            Some(ServerEvent::Started(Duration::from_millis(12345)))
+        );
+        // paper and forge write the prefix differently
+        assert_eq!(parse_line(r#"[12:00:00 INFO]: Done (3s)! For help, type "help""#), Some(ServerEvent::Started(Duration::from_secs(3))));
+        assert_eq!(
+            parse_line(r#"[12:00:00] [Server thread/INFO] [minecraft/DedicatedServer]: Done (7.5s)! For help, type "help""#),
+            Some(ServerEvent::Started(Duration::from_millis(7500)))
+        );
+    }
+
+    #[test]
+    fn players_joining_and_leaving() {
+        assert_eq!(parse_line("[12:00:00] [Server thread/INFO]: Steve_2 joined the game"), Some(ServerEvent::PlayerJoined("Steve_2".to_string())));
+        assert_eq!(parse_line("[12:00:00] [Server thread/INFO]: Steve_2 left the game\r"), Some(ServerEvent::PlayerLeft("Steve_2".to_string())));
+        assert_eq!(parse_line("[12:00:00] [Server thread/INFO]: Steve lost connection: Disconnected"), None);
+    }
+
+    #[test]
+    fn lagging() {
+        assert_eq!(
+            parse_line("[12:00:00] [Server thread/WARN]: Can't keep up! Is the server overloaded? Running 2034ms or 40 ticks behind"),
+            Some(ServerEvent::Lagging { behind_ms: 2034, ticks: 40 })
+        );
+    }
+
+    #[test]
+    fn saved() {
+        assert_eq!(parse_line("[12:00:00] [Server thread/INFO]: Saved the game"), Some(ServerEvent::Saved));
+        assert_eq!(parse_line("[12:00:00] [Server thread/INFO]: Saving the game (this may take a moment!)"), None);
+    }
+
+    #[test]
+    fn exceptions() {
+        assert_eq!(
+            parse_line("[12:00:00] [Server thread/ERROR]: Encountered an unexpected exception java.lang.IllegalStateException: Ticking entity"),
+            Some(ServerEvent::Exception("java.lang.IllegalStateException".to_string()))
+        );
+        // the head of a stack trace printed on its own line
+        assert_eq!(
+            parse_line("java.lang.OutOfMemoryError: Java heap space"),
+            Some(ServerEvent::Exception("java.lang.OutOfMemoryError".to_string()))
+        );
+        assert_eq!(parse_line("\tat net.minecraft.server.MinecraftServer.run(MinecraftServer.java:123)"), None);
+    }
+
+    #[test]
+    fn players_can_not_fake_events() {
+        for said in [
+            "<Steve> ",
+            "<Steve> ]: ",
+            "[Steve] ",
+            "[Server] ",
+            "* Steve ",
+        ] {
+            for event in [
+                "Done (1.0s)! For help, type \"help\"",
+                "Alex joined the game",
+                "Alex left the game",
+                "Can't keep up! Is the server overloaded? Running 2034ms or 40 ticks behind",
+                "Saved the game",
+                "java.lang.NullPointerException",
+            ] {
+                let line = format!("[12:00:00] [Server thread/INFO]: {}{}", said, event);
+                assert_eq!(parse_line(&line), None, "{}", line);
+            }
+        }
+    }
+
+    #[test]
+    fn other_lines() {
+        assert_eq!(parse_line("[12:00:00] [Server thread/INFO]: Preparing spawn area: 42%"), None);
+        assert_eq!(parse_line(""), None);
+    }
+}
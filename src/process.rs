@@ -1,156 +1,658 @@
-use std::{io::{BufWriter, Write}, process::{ChildStdin, Child, Command, Stdio}, time::Duration, thread::{self, JoinHandle}, fs::File, env, sync::{Arc, Mutex, mpsc::{Sender, self}}};
+use std::{fmt::{self, Display}, io::{self, BufRead, BufReader, BufWriter, Read, Write}, process::{ChildStdin, Child, Command, ExitStatus, Stdio}, time::{Duration, Instant}, thread::{self, JoinHandle}, fs::File, path::{Path, PathBuf}, sync::{Arc, Mutex, mpsc::{Receiver, RecvTimeoutError, Sender, self}}, collections::VecDeque};
 
-use tracing::{error, info};
+use chrono::{DateTime, Utc};
+use tracing::{error, info, warn};
 
-use crate::config::Args;
+use crate::{config::{Args, Config, CrashBackoff}, incident::{self, Incident}, output::{parse_line, OutputHistory, ServerEvent}, rcon::{self, Rcon, RconError, RconSettings}};
 
-pub struct Process {
-    killed: Arc<Mutex<bool>>,
-    stop_checker_thread: JoinHandle<()>,
-    send_kill_tx: Sender<bool>,
-    pub stdin: Arc<Mutex<BufWriter<ChildStdin>>>,
+/// How the server gets started
+#[derive(Debug, Clone)]
+pub enum Launch {
+    /// run the start script with the system shell
+    Script(String),
+    /// run the jar directly with the configured java binary
+    Java {
+        java: String,
+        jar: String,
+        args: Args,
+        nogui: bool,
+    },
 }
 
-impl Process {
-    pub fn new(file_name: String, server_folder: String, args: Args, nogui: bool) -> Self {
-        let valid_file = File::open(format!("{}/{}", server_folder, file_name)).is_ok(); 
-
-        if !valid_file {
-            error!("Error Accessing {} Check Config For `server_start_file`", file_name);
+impl Display for Launch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Launch::Script(file) => write!(f, "Script {}", file),
+            Launch::Java { java, jar, args, nogui } => write!(
+                f,
+                "Jar File {} With `{} {} -jar {}{}`",
+                jar,
+                java,
+                args.join(" "),
+                jar,
+                if *nogui {" nogui"} else {""}
+            ),
         }
+    }
+}
+
+/// how long the server's processes get to go away after SIGKILL
+const KILL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long the stop sequence waits at each step before escalating
+#[derive(Clone)]
+pub struct StopTimeouts {
+    /// time the server gets to save and exit after `stop` is sent
+    pub stop: Duration,
+    /// time the server gets to exit after SIGTERM before it is killed
+    pub terminate: Duration,
+}
+
+/// What the stop checker is asked to do with the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Order {
+    /// stop the server and end the stop checker
+    Exit,
+    /// stop the server then start it again
+    Restart,
+    /// stop the server and keep it down until the next restart
+    Stop,
+}
+
+/// What the stop checker has found out about crashes since the main loop last asked
+#[derive(Default)]
+struct Health {
+    /// the server crashed and was started again
+    crashed: bool,
+    /// the server crashed too often and is no longer being restarted
+    gave_up: bool,
+    /// the main loop has already been told about `gave_up`
+    gave_up_reported: bool,
+    /// the server was stopped on purpose and stays down until it is restarted
+    stopped: bool,
+    /// crashes the main loop hasn't sent notifications for yet
+    incidents: Vec<Incident>,
+    /// the server that is running now, `None` while it's down
+    running: Option<Running>,
+}
+
+/// The server process that is running now
+#[derive(Debug, Clone)]
+pub struct Running {
+    pub pid: u32,
+    pub since: DateTime<Utc>,
+    /// the folder it was started in, which holds its server.properties
+    pub folder: PathBuf,
+}
+
+impl Running {
+    fn new(process: &Child, folder: &str) -> Self {
+        Self { pid: process.id(), since: Utc::now(), folder: PathBuf::from(folder) }
+    }
+}
 
-        let production = match env::var("PRODUCTION") {
-            Ok(str) => str.as_str().parse::<bool>().expect("Error: Invalid Environment Variable PRODUCTION"),
-            Err(_) => false
+impl Health {
+    /// forgets about any crash loop, but keeps incidents that haven't been picked up yet
+    fn reset(&mut self) {
+        let incidents = std::mem::take(&mut self.incidents);
+        *self = Self {
+            incidents,
+            ..Self::default()
         };
+    }
+}
 
-        info!("Attempting To Start Jar File {} in {}", file_name, server_folder);
+/// Everything the stop checker needs from the config, swapped out by [`Process::reconfigure`] after a reload.
+/// The launch settings are only picked up when the server is next started
+#[derive(Clone)]
+struct Settings {
+    launch: Launch,
+    server_folder: String,
+    timeouts: StopTimeouts,
+    backoff: CrashBackoff,
+    incident_folder: PathBuf,
+}
 
-        let killed = Arc::new(Mutex::new(false));
-        let killed_clone = Arc::clone(&killed);
+impl Settings {
+    fn new(config: &Config) -> Self {
+        Self {
+            launch: config.launch(),
+            server_folder: config.server_folder.clone(),
+            timeouts: config.stop_timeouts(),
+            backoff: config.crash_backoff.clone(),
+            incident_folder: PathBuf::from(&config.incident_folder),
+        }
+    }
+}
 
-        let (send_kill_tx, send_kill_rx) = mpsc::channel::<bool>();
+pub struct Process {
+    health: Arc<Mutex<Health>>,
+    settings: Arc<Mutex<Settings>>,
+    /// recent server output, saved with incidents
+    output: Arc<Mutex<OutputHistory>>,
+    stop_checker_thread: JoinHandle<()>,
+    send_kill_tx: Sender<Order>,
+    event_rx: Receiver<ServerEvent>,
+    /// `None` while the server couldn't be started
+    pub stdin: Arc<Mutex<Option<BufWriter<ChildStdin>>>>,
+    /// the RCON connection along with the pid of the server it's connected to
+    rcon: Option<(u32, Rcon)>,
+    /// the pid of a server that has RCON turned off or refused the password, so it isn't tried again
+    rcon_unusable: Option<u32>,
+}
+
+impl Process {
+    /// Starts the server. Its output is kept in `output`, which outlives the process so it can be read from elsewhere
+    pub fn new(config: &Config, output: &Arc<Mutex<OutputHistory>>) -> Self {
+        let output = Arc::clone(output);
+        let settings = Arc::new(Mutex::new(Settings::new(config)));
+        let settings_clone = Arc::clone(&settings);
+        // the folder the running server was started in, which a reload can't change
+        let mut server_folder = config.server_folder.clone();
 
-        let mut process = spawn_process(&file_name, &server_folder, &args, &nogui, &production);
+        let health = Arc::new(Mutex::new(Health::default()));
+        let health_clone = Arc::clone(&health);
 
-        let stdin = Arc::new(Mutex::new(BufWriter::new(process.stdin.take().expect("Failed To Aquire STD Input for Child Process"))));
+        let (send_kill_tx, send_kill_rx) = mpsc::channel::<Order>();
+        let (event_tx, event_rx) = mpsc::channel::<ServerEvent>();
+        let output_clone = Arc::clone(&output);
+
+        let stdin = Arc::new(Mutex::new(None));
         let stdin_clone = Arc::clone(&stdin);
 
+        let mut known_reports = incident::crash_reports(Path::new(&server_folder));
+        // `None` while the server couldn't be started, which is handled like a crash
+        let mut process = start(&config.launch(), &server_folder, &event_tx, &output, &mut stdin.lock().unwrap(), &health);
+
         let stop_checker_thread = thread::Builder::new()
             .name("stop_checker".to_string())
             .spawn(move || {
-                let killed = killed_clone;
+                let health = health_clone;
+                let settings = settings_clone;
+                let output = output_clone;
+                // when each of the recent crashes happened, used to work out the backoff
+                let mut crashes = VecDeque::<Instant>::new();
                 loop {
-                    if let Ok(bool) = send_kill_rx.recv_timeout(Duration::from_secs(1)) {
+                    let order = match send_kill_rx.recv_timeout(Duration::from_secs(1)) {
+                        Ok(order) => Some(order),
+                        // the process was dropped without being killed, nobody is left to give orders so stop the server and exit
+                        Err(RecvTimeoutError::Disconnected) => Some(Order::Exit),
+                        Err(RecvTimeoutError::Timeout) => None,
+                    };
+                    if let Some(order) = order {
+                        // hold the stdin lock for the whole stop so nothing else gets written to a dying server
                         let mut stdin_clone = stdin_clone.lock().unwrap();
-                        stdin_clone.write_all("/stop".as_bytes()).unwrap();
-                        stdin_clone.flush().unwrap();
-                        if let Err(io_err) = process.try_wait() {
-                            error!("Internal Error: Error Stoping Child Process {}: Will Try Again In 30 Seconds", io_err);
-                            thread::sleep(Duration::from_secs(30));
-                            stdin_clone.write_all("/stop\n".as_bytes()).expect("Internal Error: Error While Writing To Std Input");
-                            stdin_clone.flush().unwrap();
-                            process.try_wait().expect("Internal Error: Error Retrying To Stop Child Process");
+                        let Settings { launch, server_folder: next_folder, timeouts, .. } = settings.lock().unwrap().clone();
+                        if let Some(process) = &mut process {
+                            stop_process(process, &mut stdin_clone, &timeouts);
                         }
+                        health.lock().unwrap().running = None;
 
-                        if bool {
-                            break;
+                        match order {
+                            Order::Exit => break,
+                            Order::Stop => {
+                                health.lock().unwrap().stopped = true;
+                                continue;
+                            },
+                            Order::Restart => {},
                         }
-                        else {
-                            process = spawn_process(&file_name, &server_folder, &args, &nogui, &production);
-                            let stdin = BufWriter::new(process.stdin.take().unwrap());
-                            *stdin_clone = stdin;
+                        // a requested restart is a fresh start, even after giving up on a crash loop
+                        crashes.clear();
+                        health.lock().unwrap().reset();
+
+                        // only spawn the replacement once the old server is fully gone
+                        server_folder = next_folder;
+                        known_reports = incident::crash_reports(Path::new(&server_folder));
+                        process = start(&launch, &server_folder, &event_tx, &output, &mut stdin_clone, &health);
+                        continue;
+                    }
+                    // nothing is running, wait for someone to ask for a restart
+                    let held = {
+                        let health = health.lock().unwrap();
+                        health.gave_up || health.stopped
+                    };
+                    if held {
+                        continue;
+                    }
+                    let Settings { backoff, incident_folder, launch, timeouts, .. } = settings.lock().unwrap().clone();
+                    // a server that couldn't be started was already logged by `start`, and just counts as a crash
+                    if let Some(child) = &mut process {
+                        let Ok(Some(status)) = child.try_wait() else {
                             continue;
+                        };
+                        error!("Minecraft Server Unexpectedly Stopped With {}", status);
+                        health.lock().unwrap().running = None;
+                        // whatever a start script left running would keep the world and the port from the next server
+                        if group_alive(child) {
+                            warn!("Stopping What The Minecraft Server Left Running");
+                            stop_process(child, &mut stdin_clone.lock().unwrap(), &timeouts);
+                        }
+
+                        // give the reader threads a moment to drain whatever the server printed on its way down
+                        thread::sleep(Duration::from_millis(500));
+                        let tail = output.lock().unwrap().tail(usize::MAX);
+                        match incident::collect(&incident_folder, Path::new(&server_folder), &known_reports, &status, &tail) {
+                            Ok(incident) => health.lock().unwrap().incidents.push(incident),
+                            Err(io_err) => error!("Failed To Save Crash Incident To {}: {}", incident_folder.display(), io_err),
                         }
-                    }    
-                    if let Ok(Some(_)) = process.try_wait() {
-                        info!("Minecraft Server Unexpectedly Stopped Attemping To Restart It");
-                        *killed.lock().unwrap() = true;
-                        process = spawn_process(&file_name, &server_folder, &args, &nogui, &production);
-                        let stdin = BufWriter::new(process.stdin.take().unwrap());
-                        *stdin_clone.lock().unwrap() = stdin;
+                    }
+
+                    let now = Instant::now();
+                    crashes.push_back(now);
+                    while crashes.front().is_some_and(|crash| now.duration_since(*crash) > backoff.window()) {
+                        crashes.pop_front();
+                    }
+
+                    if crashes.len() as u32 >= backoff.max_crashes {
+                        error!(
+                            "Minecraft Server Crashed {} Times In {} Seconds Giving Up On Restarting It. Use `start` To Try Again",
+                            crashes.len(),
+                            backoff.window
+                        );
+                        health.lock().unwrap().gave_up = true;
+                        continue;
+                    }
+
+                    let delay = backoff.delay(crashes.len() as u32);
+                    info!("Attemping To Restart Minecraft Server In {} Seconds ({} Crashes Recently)", delay.as_secs(), crashes.len());
+                    // keep listening while we back off so a stop or manual restart isn't held up
+                    match send_kill_rx.recv_timeout(delay) {
+                        Ok(Order::Exit) | Err(RecvTimeoutError::Disconnected) => break,
+                        Ok(Order::Restart) => crashes.clear(),
+                        Ok(Order::Stop) => {
+                            health.lock().unwrap().stopped = true;
+                            continue;
+                        },
+                        Err(RecvTimeoutError::Timeout) => {},
+                    }
+
+                    // a crashed server comes back with the same folder so the crash loop is judged on one server
+                    known_reports = incident::crash_reports(Path::new(&server_folder));
+                    process = start(&launch, &server_folder, &event_tx, &output, &mut stdin_clone.lock().unwrap(), &health);
+                    // a server that didn't start is counted as the next crash on the next pass
+                    if process.is_some() {
+                        health.lock().unwrap().crashed = true;
                     }
                 }
             })
         .unwrap();
 
         Self {
-            killed,
+            health,
+            settings,
+            output,
             stdin,
             stop_checker_thread,
             send_kill_tx,
+            event_rx,
+            rcon: None,
+            rcon_unusable: None,
         }
     }
     pub fn kill(self) {
-        let _ = self.send_kill_tx.send(true);
+        let _ = self.send_kill_tx.send(Order::Exit);
         self.stop_checker_thread.join().unwrap();
     }
 
     pub fn say(&mut self, input: String) {
-        let mut stdin = self.stdin.lock().unwrap();
-        // write the msg to the sdtin buffer
-        stdin.write_all(format!("/say {}\n", input).as_bytes()).expect("Error Writing To STD Input Buffer");
-        // flush the buffer in order to ensure the bytes get pushed to the stdin
-        stdin.flush().expect("Error Flushing STD Input Buffer");
+        self.cmd(format!("say {}", input));
     }
+    /// Sends a command and logs what the server replied when it went over RCON
     pub fn cmd(&mut self, cmd: String) {
-        let cmd = cmd.trim();
-        let mut stdin = self.stdin.lock().unwrap();
-        // write the msg to the sdtin buffer
-        stdin.write_all(format!("{}\n", cmd).as_bytes()).expect("Error Writing To STD Input Buffer");
-        // flush the buffer in order to ensure the bytes get pushed to the stdin
-        stdin.flush().expect("Error Flushing STD Input Buffer");
+        if let Some(reply) = self.send(&cmd) {
+            if !reply.trim().is_empty() {
+                info!("{}", reply.trim_end());
+            }
+        }
+    }
+    /// Sends a command over RCON when the server has it turned on, otherwise through stdin.
+    /// Returns the server's reply if it went over RCON
+    pub fn send(&mut self, cmd: &str) -> Option<String> {
+        self.send_waiting(cmd, rcon::TIMEOUT)
+    }
+    /// Sends a command like `send` but waits up to `timeout` for an RCON reply
+    pub fn send_waiting(&mut self, cmd: &str, timeout: Duration) -> Option<String> {
+        match self.rcon_waiting(cmd, timeout) {
+            Ok(reply) => Some(reply),
+            Err(err) if err.reached_server() => {
+                error!("Error Running `{}` Over RCON: {}", cmd.trim(), err);
+                None
+            },
+            Err(_) => {
+                self.write_line(cmd.trim().to_string());
+                None
+            },
+        }
+    }
+    /// Runs a command over RCON and returns what the server replied, for a server with `enable-rcon` in its server.properties.
+    /// The connection is made on first use and again after the server restarts or the connection breaks
+    pub fn rcon(&mut self, cmd: &str) -> Result<String, RconError> {
+        self.rcon_waiting(cmd, rcon::TIMEOUT)
+    }
+    fn rcon_waiting(&mut self, cmd: &str, timeout: Duration) -> Result<String, RconError> {
+        let Some(running) = self.running() else {
+            return Err(RconError::Connect(io::Error::new(io::ErrorKind::NotConnected, "the server isn't running")));
+        };
+        if self.rcon_unusable == Some(running.pid) {
+            return Err(RconError::Disabled);
+        }
+        if self.rcon.as_ref().is_some_and(|(pid, _)| *pid != running.pid) {
+            self.rcon = None;
+        }
+        if self.rcon.is_none() {
+            let settings = RconSettings::load(&running.folder).ok_or(RconError::Disabled);
+            match settings.and_then(|settings| Rcon::connect(&settings)) {
+                Ok(rcon) => {
+                    info!("Connected To RCON");
+                    self.rcon = Some((running.pid, rcon));
+                },
+                Err(err @ (RconError::Disabled | RconError::AuthFailed)) => {
+                    if let RconError::AuthFailed = err {
+                        error!("Error Connecting To RCON: {} Using stdin Instead", err);
+                    }
+                    self.rcon_unusable = Some(running.pid);
+                    return Err(err);
+                },
+                Err(err) => return Err(err),
+            }
+        }
+        let (_, rcon) = self.rcon.as_mut().expect("Connected above");
+        let result = rcon.command(cmd.trim().trim_start_matches('/'), timeout);
+        // a broken connection is made again next time
+        if result.as_ref().is_err_and(RconError::reached_server) {
+            self.rcon = None;
+        }
+        result
+    }
+    fn write_line(&mut self, line: String) {
+        // the stop checker holds stdin for the whole stop, and waiting for it would hold up the control loop
+        let Ok(mut stdin) = self.stdin.try_lock() else {
+            warn!("Not Sending `{}` While The Minecraft Server Is Stopping", line);
+            return;
+        };
+        let Some(stdin) = stdin.as_mut() else {
+            error!("Error Writing `{}` To Server: It Isn't Running", line);
+            return;
+        };
+        // write the msg to the sdtin buffer then flush the buffer in order to ensure the bytes get pushed to the stdin.
+        // The pipe is broken while the server is down, which isn't worth taking the bouncer down over
+        if let Err(io_err) = stdin.write_all(format!("{}\n", line).as_bytes()).and_then(|_| stdin.flush()) {
+            error!("Error Writing `{}` To Server: {}", line, io_err);
+        }
     }
     pub fn restart(&mut self) {
-        let _ = self.send_kill_tx.send(false); 
-    }
-    pub fn is_stopped(&self) -> bool {
-        *self.killed.lock().unwrap()
-    }
-}
-
-fn spawn_process(jar_file: &String, server_folder: &str, _args: &Args, _nogui: &bool, production: &bool) -> Child {
-    #[cfg(target_os = "windows")]
-    let mut binding = Command::new("cmd");
-    #[cfg(target_os = "windows")]
-    let command = binding
-    .arg("/C")
-    .arg(jar_file)
-    .current_dir(if *production {server_folder} else {"debug server"})
-    .stdin(Stdio::piped());
-
-    #[cfg(target_os = "unix")]
-    let mut binding = Command::new("sh");
-    #[cfg(target_os = "unix")]
-    let command = binding
-            .arg(jar_file)
-            .current_dir(if *production {server_folder} else {"debug server"})
-            .stdin(Stdio::piped());
-
-    #[cfg(target_os = "linux")]
-    let mut binding = Command::new("sh");
-    #[cfg(target_os = "linux")]
-    let command = binding
-            .arg(jar_file)
-            .current_dir(if *production {server_folder} else {"debug server"})
-            .stdin(Stdio::piped());
-    #[cfg(target_os = "macos")]
-    let mut binding = Command::new("sh");
-    #[cfg(target_os = "macos")]
-    let command = binding
-            .arg(jar_file)
-            .current_dir(if *production {server_folder} else {"debug server"})
-            .stdin(Stdio::piped());
-
-    command.spawn().expect("Failed to start server")
-    // Command::new("sh")
-    //     .current_dir(if *production {server_folder} else {"debug server"})
-    //     .args(args)
-    //     .arg("-jar")
-    //     .arg(jar_file)
-    //     .arg(if *nogui {"nogui"} else {""})
-    //     .stdin(Stdio::piped())
-    //     // .stdout(Stdio::piped())
-    //     .spawn()
-    //     .expect("Failed to start java process")
+        let _ = self.send_kill_tx.send(Order::Restart); 
+    }
+    /// Stops the server and keeps it down until [`Process::restart`], without blocking.
+    /// [`Process::stopped`] says when it's done
+    pub fn stop(&mut self) {
+        let _ = self.send_kill_tx.send(Order::Stop);
+    }
+    /// whether the server was stopped by [`Process::stop`] and hasn't been restarted since
+    pub fn stopped(&self) -> bool {
+        self.health.lock().unwrap().stopped
+    }
+    /// Uses a reloaded config from now on. Stop timeouts and crash handling change right away,
+    /// how the server is launched changes the next time it is restarted
+    pub fn reconfigure(&self, config: &Config) {
+        *self.settings.lock().unwrap() = Settings::new(config);
+        self.output.lock().unwrap().set_capacity(config.incident_log_lines);
+    }
+    /// whether the server crashed and was started again since the last time this was called
+    pub fn take_crashed(&self) -> bool {
+        std::mem::take(&mut self.health.lock().unwrap().crashed)
+    }
+
+    /// the crashes collected since the last time this was called
+    pub fn take_incidents(&self) -> Vec<Incident> {
+        std::mem::take(&mut self.health.lock().unwrap().incidents)
+    }
+
+    /// whether the server crashed too many times and is no longer being restarted.
+    /// Only reports true once per crash loop
+    pub fn take_gave_up(&self) -> bool {
+        let mut health = self.health.lock().unwrap();
+        if health.gave_up && !health.gave_up_reported {
+            health.gave_up_reported = true;
+            return true;
+        }
+        false
+    }
+
+    /// the server that is running now, `None` while it's down
+    pub fn running(&self) -> Option<Running> {
+        self.health.lock().unwrap().running.clone()
+    }
+
+    /// grabs the next event parsed from the server output, if there is one
+    pub fn new_event(&mut self) -> Option<ServerEvent> {
+        self.event_rx.try_recv().ok()
+    }
+}
+
+/// Stops the server without ever leaving it running behind our back.
+///
+/// Sends `save-all` and `stop` then waits for the server to exit. If it doesn't exit within
+/// [`StopTimeouts::stop`] it gets SIGTERM, and if that is ignored for [`StopTimeouts::terminate`] it gets killed.
+/// This only returns once the process has exited
+fn stop_process(process: &mut Child, stdin: &mut Option<BufWriter<ChildStdin>>, timeouts: &StopTimeouts) -> Option<ExitStatus> {
+    // the server may already be gone, in which case the pipe is broken and there is nothing to stop
+    if let Some(status) = wait_timeout(process, Duration::ZERO) {
+        info!("Minecraft Server Already Stopped {}", status);
+        return Some(status);
+    }
+
+    info!("Saving And Stopping Minecraft Server");
+    // java started by a script that already exited still reads the same pipe
+    if let Some(stdin) = stdin {
+        if let Err(io_err) = stdin.write_all(b"save-all\nstop\n").and_then(|_| stdin.flush()) {
+            error!("Error Writing Stop Command To Server {}", io_err);
+        }
+    }
+    if let Some(status) = wait_timeout(process, timeouts.stop) {
+        info!("Minecraft Server Stopped {}", status);
+        return Some(status);
+    }
+
+    warn!("Minecraft Server Did Not Stop Within {} Seconds Sending SIGTERM", timeouts.stop.as_secs());
+    terminate(process);
+    if let Some(status) = wait_timeout(process, timeouts.terminate) {
+        info!("Minecraft Server Terminated {}", status);
+        return Some(status);
+    }
+
+    error!("Minecraft Server Did Not Terminate Within {} Seconds Killing It", timeouts.terminate.as_secs());
+    kill(process);
+    if let Err(io_err) = process.wait() {
+        error!("Internal Error: Error Waiting On Killed Child Process {}", io_err);
+        return None;
+    }
+    // SIGKILL can't be ignored, the rest of the group is gone as soon as it's reaped
+    let status = wait_timeout(process, KILL_TIMEOUT);
+    match status {
+        Some(status) => info!("Minecraft Server Killed {}", status),
+        None => error!("Internal Error: Minecraft Server Process Group Still Running After SIGKILL"),
+    }
+    status
+}
+
+/// Polls the process until it and everything else in its group has exited, or the timeout elapses.
+/// Returns how the process we started exited
+fn wait_timeout(process: &mut Child, timeout: Duration) -> Option<ExitStatus> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        match process.try_wait() {
+            // a start script can exit before the java it started
+            Ok(Some(status)) if !group_alive(process) => return Some(status),
+            Ok(_) => {},
+            Err(io_err) => error!("Internal Error: Error Checking Child Process {}", io_err),
+        }
+        if Instant::now() >= deadline {
+            return None;
+        }
+        thread::sleep(Duration::from_millis(250));
+    }
+}
+
+/// Sends a signal to every process in the server's group, the group id being the pid of the process we started
+#[cfg(unix)]
+fn signal_group(process: &Child, signal: libc::c_int) -> io::Result<()> {
+    // SAFETY: kill only sends a signal, and the group was made for our own child in `spawn_process`
+    match unsafe { libc::kill(-(process.id() as libc::pid_t), signal) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// whether anything the server started is still running, like the java behind a start script that already exited
+#[cfg(unix)]
+fn group_alive(process: &Child) -> bool {
+    signal_group(process, 0).is_ok()
+}
+
+#[cfg(not(unix))]
+fn group_alive(_process: &Child) -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn terminate(process: &mut Child) {
+    if let Err(io_err) = signal_group(process, libc::SIGTERM) {
+        error!("Internal Error: Error Sending SIGTERM To Child Process {}", io_err);
+    }
+}
+
+#[cfg(not(unix))]
+fn terminate(process: &mut Child) {
+    // there is no gentler signal than killing the process on windows
+    if let Err(io_err) = process.kill() {
+        error!("Internal Error: Error Killing Child Process {}", io_err);
+    }
+}
+
+#[cfg(unix)]
+fn kill(process: &mut Child) {
+    if let Err(io_err) = signal_group(process, libc::SIGKILL) {
+        error!("Internal Error: Error Killing Child Process {}", io_err);
+    }
+}
+
+#[cfg(not(unix))]
+fn kill(process: &mut Child) {
+    if let Err(io_err) = process.kill() {
+        error!("Internal Error: Error Killing Child Process {}", io_err);
+    }
+}
+
+/// Spawns the server and hooks up its output and stdin. `None` if it couldn't be started, which is logged
+fn start(
+    launch: &Launch,
+    server_folder: &str,
+    event_tx: &Sender<ServerEvent>,
+    output: &Arc<Mutex<OutputHistory>>,
+    stdin: &mut Option<BufWriter<ChildStdin>>,
+    health: &Mutex<Health>,
+) -> Option<Child> {
+    match spawn_process(launch, server_folder) {
+        Ok(mut process) => {
+            attach_output(&mut process, event_tx, output);
+            *stdin = process.stdin.take().map(BufWriter::new);
+            health.lock().unwrap().running = Some(Running::new(&process, server_folder));
+            Some(process)
+        },
+        Err(io_err) => {
+            error!("Error Starting Minecraft Server In {}: {}", server_folder, io_err);
+            *stdin = None;
+            None
+        },
+    }
+}
+
+/// Takes the stdout and stderr pipes of the child and spawns a reader thread for each.
+/// Every line is written to our own terminal unchanged, then parsed into a [`ServerEvent`] and kept in the output history
+fn attach_output(process: &mut Child, event_tx: &Sender<ServerEvent>, output: &Arc<Mutex<OutputHistory>>) {
+    if let Some(stdout) = process.stdout.take() {
+        spawn_reader("stdout_reader", stdout, io::stdout, event_tx.clone(), Arc::clone(output));
+    }
+    if let Some(stderr) = process.stderr.take() {
+        spawn_reader("stderr_reader", stderr, io::stderr, event_tx.clone(), Arc::clone(output));
+    }
+}
+
+fn spawn_reader<R, W>(name: &str, pipe: R, tee: fn() -> W, event_tx: Sender<ServerEvent>, output: Arc<Mutex<OutputHistory>>)
+    where R: Read + Send + 'static, W: Write + 'static
+    {
+    thread::Builder::new()
+        .name(name.to_string())
+        .spawn(move || {
+            let mut reader = BufReader::new(pipe);
+            let mut line = Vec::new();
+            loop {
+                line.clear();
+                // the pipe is closed once the child exits, which ends the thread
+                match reader.read_until(b'\n', &mut line) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => {}
+                }
+                let mut tee = tee();
+                let _ = tee.write_all(&line);
+                let _ = tee.flush();
+
+                let line = String::from_utf8_lossy(&line).trim_end().to_string();
+                // keep draining the pipe even if nobody is listening anymore so the server never blocks on a full pipe
+                if let Some(event) = parse_line(&line) {
+                    let _ = event_tx.send(event);
+                }
+                output.lock().unwrap().push(line);
+            }
+        })
+        .unwrap();
+}
+
+/// Starts the server in its own process group, so a start script and the java it runs can be stopped together
+fn spawn_process(launch: &Launch, server_folder: &str) -> io::Result<Child> {
+    let (file_name, config_field) = match launch {
+        Launch::Script(file) => (file, "server_start_file"),
+        Launch::Java { jar, .. } => (jar, "jar_file_name"),
+    };
+    let valid_file = File::open(format!("{}/{}", server_folder, file_name)).is_ok(); 
+
+    if !valid_file {
+        error!("Error Accessing {} Check Config For `{}`", file_name, config_field);
+    }
+
+    info!("Attempting To Start {} in {}", launch, server_folder);
+
+    let mut command = match launch {
+        #[cfg(target_os = "windows")]
+        Launch::Script(file) => {
+            let mut command = Command::new("cmd");
+            command.arg("/C").arg(file);
+            command
+        },
+        #[cfg(not(target_os = "windows"))]
+        Launch::Script(file) => {
+            let mut command = Command::new("sh");
+            command.arg(file);
+            command
+        },
+        Launch::Java { java, jar, args, nogui } => {
+            let mut command = Command::new(java);
+            command.args(args).arg("-jar").arg(jar);
+            if *nogui {
+                command.arg("nogui");
+            }
+            command
+        },
+    };
+
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+
+    command
+        .current_dir(server_folder)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
 }
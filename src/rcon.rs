@@ -0,0 +1,352 @@
+use std::{collections::HashMap, error::Error, fmt::{self, Display}, fs, io::{self, Read, Write}, net::{TcpStream, ToSocketAddrs}, path::Path, time::Duration};
+
+/// packet types of the source RCON protocol, which minecraft uses
+const LOGIN: i32 = 3;
+const COMMAND: i32 = 2;
+const AUTH_RESPONSE: i32 = 2;
+const RESPONSE: i32 = 0;
+/// how long the server gets to accept the connection or reply to a command that doesn't ask for longer
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+/// the server drops the connection when sent a longer command
+const MAX_COMMAND: usize = 1446;
+/// nothing the server sends comes near this, so anything bigger means the stream is out of step
+const MAX_PACKET: usize = 1 << 20;
+
+/// Where the server listens for RCON, from its `server.properties`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RconSettings {
+    pub address: String,
+    pub password: String,
+}
+
+impl RconSettings {
+    /// `None` unless `enable-rcon=true` and an `rcon.password` are set
+    pub fn load(server_folder: &Path) -> Option<Self> {
+        let properties = parse_properties(&fs::read_to_string(server_folder.join("server.properties")).ok()?);
+        if properties.get("enable-rcon").map(String::as_str) != Some("true") {
+            return None;
+        }
+        let password = properties.get("rcon.password").filter(|password| !password.is_empty())?.clone();
+        let port = properties.get("rcon.port").and_then(|port| port.parse::<u16>().ok()).unwrap_or(25575);
+        // RCON listens on `server-ip` when it's set, otherwise on every address
+        let host = properties.get("server-ip").map(String::as_str).filter(|ip| !ip.is_empty()).unwrap_or("127.0.0.1");
+        Some(Self { address: format!("{}:{}", host, port), password })
+    }
+}
+
+/// Reads the `key=value` lines of a java properties file, skipping comments
+pub fn parse_properties(properties: &str) -> HashMap<String, String> {
+    properties
+        .lines()
+        .map(str::trim_start)
+        .filter(|line| !line.starts_with('#') && !line.starts_with('!'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (unescape(key.trim()), unescape(value.trim())))
+        .collect()
+}
+
+/// java escapes characters like `:` and `=` with a backslash when it writes the file
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some('u') => {
+                let code = chars.by_ref().take(4).collect::<String>();
+                unescaped.extend(u32::from_str_radix(&code, 16).ok().and_then(char::from_u32));
+            },
+            Some(escaped) => unescaped.push(escaped),
+            None => {},
+        }
+    }
+    unescaped
+}
+
+#[derive(Debug)]
+pub enum RconError {
+    /// `enable-rcon` isn't set in server.properties
+    Disabled,
+    /// the server couldn't be reached, usually because it is down or still starting
+    Connect(io::Error),
+    /// the server didn't accept `rcon.password`
+    AuthFailed,
+    /// the command is longer than the server accepts
+    TooLong(usize),
+    /// the connection broke or timed out after the command was sent
+    Io(io::Error),
+    /// the server sent something that isn't an RCON packet
+    Malformed(String),
+}
+
+impl RconError {
+    /// Whether the command may have reached the server. If it didn't, it can be sent another way
+    pub fn reached_server(&self) -> bool {
+        matches!(self, RconError::Io(_) | RconError::Malformed(_))
+    }
+}
+
+impl Display for RconError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RconError::Disabled => write!(f, "enable-rcon isn't set in server.properties"),
+            RconError::Connect(io_err) => write!(f, "couldn't connect: {}", io_err),
+            RconError::AuthFailed => write!(f, "the server refused rcon.password"),
+            RconError::TooLong(len) => write!(f, "command is {} bytes which is more than the {} the server accepts", len, MAX_COMMAND),
+            RconError::Io(io_err) => write!(f, "{}", io_err),
+            RconError::Malformed(msg) => write!(f, "malformed packet: {}", msg),
+        }
+    }
+}
+
+impl Error for RconError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RconError::Connect(io_err) | RconError::Io(io_err) => Some(io_err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RconError {
+    fn from(io_err: io::Error) -> Self {
+        RconError::Io(io_err)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Packet {
+    id: i32,
+    kind: i32,
+    body: String,
+}
+
+impl Packet {
+    /// the packet as it's sent, its length then the id, type and nul terminated body followed by an empty string
+    fn encode(&self) -> Vec<u8> {
+        let mut packet = Vec::with_capacity(self.body.len() + 14);
+        packet.extend_from_slice(&(self.body.len() as i32 + 10).to_le_bytes());
+        packet.extend_from_slice(&self.id.to_le_bytes());
+        packet.extend_from_slice(&self.kind.to_le_bytes());
+        packet.extend_from_slice(self.body.as_bytes());
+        packet.extend_from_slice(&[0, 0]);
+        packet
+    }
+
+    fn read(reader: &mut impl Read) -> Result<Self, RconError> {
+        let mut int = [0; 4];
+        reader.read_exact(&mut int)?;
+        let len = i32::from_le_bytes(int);
+        if !(10..=MAX_PACKET as i32).contains(&len) {
+            return Err(RconError::Malformed(format!("length {}", len)));
+        }
+        let mut packet = vec![0; len as usize];
+        reader.read_exact(&mut packet)?;
+        let id = i32::from_le_bytes(packet[0..4].try_into().expect("4 bytes"));
+        let kind = i32::from_le_bytes(packet[4..8].try_into().expect("4 bytes"));
+        // the body ends with two nul bytes
+        let body = String::from_utf8_lossy(&packet[8..packet.len() - 2]).into_owned();
+        Ok(Self { id, kind, body })
+    }
+}
+
+/// A logged in RCON connection to the server
+pub struct Rcon {
+    stream: TcpStream,
+    next_id: i32,
+}
+
+impl Rcon {
+    pub fn connect(settings: &RconSettings) -> Result<Self, RconError> {
+        let address = settings
+            .address
+            .to_socket_addrs()
+            .map_err(RconError::Connect)?
+            .next()
+            .ok_or_else(|| RconError::Connect(io::Error::new(io::ErrorKind::NotFound, format!("{} has no address", settings.address))))?;
+        let stream = TcpStream::connect_timeout(&address, TIMEOUT).map_err(RconError::Connect)?;
+        stream.set_read_timeout(Some(TIMEOUT)).map_err(RconError::Connect)?;
+        stream.set_write_timeout(Some(TIMEOUT)).map_err(RconError::Connect)?;
+        let mut rcon = Self { stream, next_id: 1 };
+
+        let id = rcon.send(LOGIN, &settings.password).map_err(RconError::Connect)?;
+        loop {
+            let packet = rcon.receive().map_err(|err| match err {
+                RconError::Io(io_err) => RconError::Connect(io_err),
+                err => err,
+            })?;
+            if packet.kind != AUTH_RESPONSE {
+                continue;
+            }
+            if packet.id == -1 {
+                return Err(RconError::AuthFailed);
+            }
+            if packet.id == id {
+                return Ok(rcon);
+            }
+        }
+    }
+
+    /// Runs a command and returns the server's reply, waiting up to `timeout` for it. A long reply is split over several
+    /// packets, so an empty packet is sent after the command and the reply is read until the server answers that one
+    pub fn command(&mut self, command: &str, timeout: Duration) -> Result<String, RconError> {
+        if command.len() > MAX_COMMAND {
+            return Err(RconError::TooLong(command.len()));
+        }
+        self.stream.set_read_timeout(Some(timeout)).map_err(RconError::Io)?;
+        let id = self.send(COMMAND, command)?;
+        let end = self.send(RESPONSE, "")?;
+        let mut reply = String::new();
+        loop {
+            let packet = self.receive()?;
+            if packet.id == end {
+                return Ok(reply);
+            }
+            if packet.id == id {
+                reply.push_str(&packet.body);
+            }
+        }
+    }
+
+    /// Writes a packet and returns its id
+    fn send(&mut self, kind: i32, body: &str) -> io::Result<i32> {
+        let id = self.next_id;
+        // -1 is how the server says the password was wrong
+        self.next_id = self.next_id.checked_add(1).unwrap_or(1);
+        self.stream.write_all(&Packet { id, kind, body: body.to_string() }.encode())?;
+        Ok(id)
+    }
+
+    fn receive(&mut self) -> Result<Packet, RconError> {
+        Packet::read(&mut self.stream)
+    }
+}
+
+/// Reads the player names from the reply to `list`, like `There are 2 of a max of 20 players online: Steve, Alex`
+pub fn parse_list(reply: &str) -> Option<Vec<String>> {
+    let (_, names) = reply.split_once(':')?;
+    Some(names.split(',').map(str::trim).filter(|name| !name.is_empty()).map(str::to_string).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, thread};
+
+    use super::*;
+
+    /// Pretends to be a minecraft server on a free port. It accepts `password` and answers a command with `reply`
+    /// split over packets after `delay`, then answers the empty packet after it like minecraft does
+    fn fake_server(password: &'static str, reply: &'static [&'static str], delay: Duration) -> RconSettings {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let login = Packet::read(&mut stream).unwrap();
+            assert_eq!(login.kind, LOGIN);
+            let id = if login.body == password { login.id } else { -1 };
+            stream.write_all(&Packet { id, kind: AUTH_RESPONSE, body: String::new() }.encode()).unwrap();
+            if id == -1 {
+                return;
+            }
+            let command = Packet::read(&mut stream).unwrap();
+            let end = Packet::read(&mut stream).unwrap();
+            assert_eq!((command.kind, end.kind, end.body.as_str()), (COMMAND, RESPONSE, ""));
+            thread::sleep(delay);
+            for part in reply {
+                let _ = stream.write_all(&Packet { id: command.id, kind: RESPONSE, body: part.to_string() }.encode());
+            }
+            // the client may have given up waiting and gone
+            let _ = stream.write_all(&Packet { id: end.id, kind: RESPONSE, body: "Unknown request 0".to_string() }.encode());
+        });
+        RconSettings { address, password: "secret".to_string() }
+    }
+
+    #[test]
+    fn encodes_a_packet() {
+        let packet = Packet { id: 7, kind: COMMAND, body: "list".to_string() };
+        assert_eq!(packet.encode(), [14, 0, 0, 0, 7, 0, 0, 0, 2, 0, 0, 0, b'l', b'i', b's', b't', 0, 0]);
+        assert_eq!(Packet::read(&mut packet.encode().as_slice()).unwrap(), packet);
+    }
+
+    #[test]
+    fn rejects_a_packet_with_a_bad_length() {
+        let mut short = 9i32.to_le_bytes().to_vec();
+        short.extend_from_slice(&[0; 9]);
+        assert!(matches!(Packet::read(&mut short.as_slice()), Err(RconError::Malformed(_))));
+        let huge = (MAX_PACKET as i32 + 1).to_le_bytes();
+        assert!(matches!(Packet::read(&mut huge.as_slice()), Err(RconError::Malformed(_))));
+    }
+
+    #[test]
+    fn joins_a_reply_split_over_packets_up_to_the_end_marker() {
+        let settings = fake_server("secret", &["There are 2 of a max of 20 players online: ", "Steve, ", "Alex"], Duration::ZERO);
+        let mut rcon = Rcon::connect(&settings).unwrap();
+        assert_eq!(rcon.command("list", TIMEOUT).unwrap(), "There are 2 of a max of 20 players online: Steve, Alex");
+    }
+
+    #[test]
+    fn an_empty_reply_ends_at_the_end_marker() {
+        let settings = fake_server("secret", &[], Duration::ZERO);
+        assert_eq!(Rcon::connect(&settings).unwrap().command("save-all", TIMEOUT).unwrap(), "");
+    }
+
+    #[test]
+    fn waits_as_long_as_asked_for_a_slow_reply() {
+        let settings = fake_server("secret", &["Saved the game"], Duration::from_millis(500));
+        let mut rcon = Rcon::connect(&settings).unwrap();
+        let err = rcon.command("save-all flush", Duration::from_millis(50)).unwrap_err();
+        assert!(err.reached_server(), "{}", err);
+
+        let settings = fake_server("secret", &["Saved the game"], Duration::from_millis(500));
+        let mut rcon = Rcon::connect(&settings).unwrap();
+        assert_eq!(rcon.command("save-all flush", Duration::from_secs(5)).unwrap(), "Saved the game");
+    }
+
+    #[test]
+    fn a_wrong_password_fails_to_log_in() {
+        let settings = fake_server("other", &[], Duration::ZERO);
+        assert!(matches!(Rcon::connect(&settings), Err(RconError::AuthFailed)));
+    }
+
+    #[test]
+    fn parses_the_list_reply() {
+        // 1.13 and later
+        assert_eq!(parse_list("There are 2 of a max of 20 players online: Steve, Alex").unwrap(), ["Steve", "Alex"]);
+        assert_eq!(parse_list("There are 0 of a max of 20 players online: ").unwrap(), Vec::<String>::new());
+        // 1.12 and earlier put the names on the next line
+        assert_eq!(parse_list("There are 2/20 players online:\nSteve, Alex").unwrap(), ["Steve", "Alex"]);
+        assert_eq!(parse_list("There are 0/20 players online:\n").unwrap(), Vec::<String>::new());
+        assert_eq!(parse_list("Unknown command"), None);
+    }
+
+    /// the settings loaded from a server.properties holding `properties`
+    fn load(name: &str, properties: &str) -> Option<RconSettings> {
+        let folder = std::env::temp_dir().join(format!("bouncer_rcon_test_{}_{}", std::process::id(), name));
+        fs::create_dir_all(&folder).unwrap();
+        fs::write(folder.join("server.properties"), properties).unwrap();
+        let settings = RconSettings::load(&folder);
+        fs::remove_dir_all(&folder).unwrap();
+        settings
+    }
+
+    #[test]
+    fn loads_settings_from_server_properties() {
+        assert_eq!(
+            load("enabled", "#Minecraft server properties\nenable-rcon=true\nrcon.password=se\\=cr\\:et\nrcon.port=25580\nserver-ip=\n"),
+            Some(RconSettings { address: "127.0.0.1:25580".to_string(), password: "se=cr:et".to_string() })
+        );
+        assert_eq!(load("server_ip", "enable-rcon=true\nrcon.password=secret\nserver-ip=10.0.0.2\n").unwrap().address, "10.0.0.2:25575");
+    }
+
+    #[test]
+    fn loads_nothing_without_rcon_turned_on() {
+        assert_eq!(load("disabled", "enable-rcon=false\nrcon.password=secret\n"), None);
+        assert_eq!(load("no_password", "enable-rcon=true\nrcon.password=\n"), None);
+        assert_eq!(RconSettings::load(&std::env::temp_dir().join("bouncer_rcon_test_missing")), None);
+    }
+}
@@ -0,0 +1,390 @@
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt::{self, Display},
+    fs::{self, File},
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+};
+
+use chrono::{Local, NaiveDateTime};
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+use zip::{result::ZipError, ZipArchive};
+
+use crate::{
+    backup::{Archive, ArchiveFormat},
+    config::Config,
+    retention::{self, TIMESTAMP_FORMAT},
+    target::TargetError,
+};
+
+/// the file in `backup_folder` remembering which targets each backup was uploaded to
+const INDEX_FILE_NAME: &str = "index.json";
+
+/// A backup that was uploaded somewhere, kept so it can still be restored after the local copy is pruned
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IndexEntry {
+    pub name: String,
+    pub archive_bytes: u64,
+    /// every target the archive was uploaded to without errors
+    pub targets: Vec<String>,
+}
+
+/// A backup that can be restored, found in `backup_folder` or the index
+#[derive(Serialize, Debug, Clone)]
+pub struct KnownBackup {
+    /// the archive's timestamp, used to pick it with `restore <id>`
+    pub id: String,
+    pub name: String,
+    pub created: NaiveDateTime,
+    pub format: ArchiveFormat,
+    pub archive_bytes: u64,
+    /// the copy in `backup_folder`, if it hasn't been pruned
+    pub local: Option<PathBuf>,
+    /// the targets holding a copy
+    pub targets: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum RestoreError {
+    /// no backup has this id
+    UnknownBackup(String),
+    /// the archive only exists remotely and none of the targets it was uploaded to could provide it
+    Download(String, Vec<TargetError>),
+    Io { path: PathBuf, source: io::Error },
+    Zip { path: PathBuf, source: ZipError },
+}
+
+impl Display for RestoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RestoreError::UnknownBackup(id) => write!(f, "there is no backup with the id {}. Use `restore list` to see them", id),
+            RestoreError::Download(name, errors) if errors.is_empty() => {
+                write!(f, "{} isn't in the backup folder and none of the targets it was uploaded to are configured anymore", name)
+            }
+            RestoreError::Download(name, errors) => write!(
+                f,
+                "failed to download {}: {}",
+                name,
+                errors.iter().map(|err| err.to_string()).collect::<Vec<_>>().join(", ")
+            ),
+            RestoreError::Io { path, source } => write!(f, "failed to restore {}: {}", path.display(), source),
+            RestoreError::Zip { path, source } => write!(f, "failed to unzip {}: {}", path.display(), source),
+        }
+    }
+}
+
+impl Error for RestoreError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RestoreError::Io { source, .. } => Some(source),
+            RestoreError::Zip { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// What a finished restore did
+pub struct Restored {
+    pub backup: KnownBackup,
+    /// where the server folder that was replaced was moved to
+    pub safety_copy: PathBuf,
+}
+
+fn index_path(config: &Config) -> PathBuf {
+    Path::new(&config.backup_folder).join(INDEX_FILE_NAME)
+}
+
+fn read_index(config: &Config) -> Vec<IndexEntry> {
+    let path = index_path(config);
+    match fs::read_to_string(&path) {
+        Ok(data) => serde_json::from_str(&data).unwrap_or_else(|err| {
+            warn!("Ignoring unreadable backup index {}: {}", path.display(), err);
+            Vec::new()
+        }),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Adds a freshly uploaded archive to the index. Archives that didn't reach any target are only ever local so they're left out
+pub fn record(config: &Config, archive: &Archive) {
+    let targets = archive
+        .uploads
+        .iter()
+        .filter(|upload| upload.result.is_ok())
+        .map(|upload| upload.target.clone())
+        .collect::<Vec<_>>();
+    if targets.is_empty() {
+        return;
+    }
+    let mut index = read_index(config);
+    index.push(IndexEntry {
+        name: archive.path.file_name().expect("archives are files").to_string_lossy().to_string(),
+        archive_bytes: archive.archive_bytes,
+        targets,
+    });
+    let path = index_path(config);
+    let result = serde_json::to_string_pretty(&index)
+        .map_err(io::Error::from)
+        .and_then(|data| fs::write(&path, data));
+    if let Err(io_err) = result {
+        error!("Failed to update the backup index {}: {}", path.display(), io_err);
+    }
+}
+
+/// Every backup in `backup_folder` or the index, newest first
+pub fn list(config: &Config) -> Vec<KnownBackup> {
+    let mut backups = BTreeMap::<NaiveDateTime, KnownBackup>::new();
+    for entry in read_index(config) {
+        let Some((created, format)) = retention::parse_archive_name(&entry.name, &config.backup_file_name) else {
+            continue;
+        };
+        backups.insert(created, KnownBackup {
+            id: created.format(TIMESTAMP_FORMAT).to_string(),
+            name: entry.name,
+            created,
+            format,
+            archive_bytes: entry.archive_bytes,
+            local: None,
+            targets: entry.targets,
+        });
+    }
+    match retention::list_backups(Path::new(&config.backup_folder), &config.backup_file_name) {
+        Ok(local) => {
+            for file in local {
+                let backup = backups.entry(file.created).or_insert_with(|| KnownBackup {
+                    id: file.created.format(TIMESTAMP_FORMAT).to_string(),
+                    name: file.path.file_name().expect("archives are files").to_string_lossy().to_string(),
+                    created: file.created,
+                    format: file.format,
+                    archive_bytes: 0,
+                    local: None,
+                    targets: Vec::new(),
+                });
+                if let Ok(meta) = fs::metadata(&file.path) {
+                    backup.archive_bytes = meta.len();
+                }
+                backup.local = Some(file.path);
+            }
+        }
+        Err(io_err) => warn!("Failed to read the backup folder {}: {}", config.backup_folder, io_err),
+    }
+    backups.into_values().rev().collect()
+}
+
+pub fn find(config: &Config, id: &str) -> Option<KnownBackup> {
+    list(config).into_iter().find(|backup| backup.id == id)
+}
+
+/// Replaces the server folder with the contents of the backup `id`. The server must not be running.
+///
+/// The current server folder is moved aside first and put back if the archive can't be extracted
+pub fn restore(config: &Config, id: &str) -> Result<Restored, RestoreError> {
+    let backup = find(config, id).ok_or_else(|| RestoreError::UnknownBackup(id.to_string()))?;
+    let archive = match &backup.local {
+        Some(path) => path.clone(),
+        None => download(config, &backup)?,
+    };
+
+    let server_folder = PathBuf::from(&config.server_folder);
+    let safety_copy = PathBuf::from(format!(
+        "{} before restore {}",
+        config.server_folder.trim_end_matches(['/', '\\']),
+        Local::now().format(TIMESTAMP_FORMAT)
+    ));
+    info!("Moving {} aside to {}", server_folder.display(), safety_copy.display());
+    fs::rename(&server_folder, &safety_copy).map_err(|source| RestoreError::Io { path: server_folder.clone(), source })?;
+
+    if let Err(err) = extract(&archive, backup.format, &server_folder) {
+        error!("Failed to extract {}, putting the old server folder back", archive.display());
+        if let Err(io_err) = fs::remove_dir_all(&server_folder).and_then(|_| fs::rename(&safety_copy, &server_folder)) {
+            error!("Failed to put {} back, it is still at {}: {}", server_folder.display(), safety_copy.display(), io_err);
+        }
+        return Err(err);
+    }
+    info!("Restored {} into {}", archive.display(), server_folder.display());
+    Ok(Restored { backup, safety_copy })
+}
+
+/// fetches a backup that was pruned locally from the first target that has it, into `backup_folder`
+fn download(config: &Config, backup: &KnownBackup) -> Result<PathBuf, RestoreError> {
+    let folder = Path::new(&config.backup_folder);
+    fs::create_dir_all(folder).map_err(|source| RestoreError::Io { path: folder.to_path_buf(), source })?;
+    let dest = folder.join(&backup.name);
+    let partial = folder.join(format!("{}.part", backup.name));
+
+    let mut errors = Vec::new();
+    let targets = config.backup_targets.iter().filter(|target| backup.targets.contains(&target.to_string()));
+    for target in targets {
+        match target.download(&backup.name, &partial) {
+            Ok(_) => {
+                fs::rename(&partial, &dest).map_err(|source| RestoreError::Io { path: dest.clone(), source })?;
+                return Ok(dest);
+            }
+            Err(err) => {
+                warn!("Failed to download {} from {}: {}", backup.name, target, err);
+                errors.push(err);
+            }
+        }
+    }
+    let _ = fs::remove_file(&partial);
+    Err(RestoreError::Download(backup.name.clone(), errors))
+}
+
+/// Unpacks `archive` into the new folder `dir`. Entries trying to escape `dir` are skipped
+pub fn extract(archive: &Path, format: ArchiveFormat, dir: &Path) -> Result<(), RestoreError> {
+    info!("Extracting {} into {}", archive.display(), dir.display());
+    let io_err = |path: &Path| {
+        let path = path.to_path_buf();
+        move |source| RestoreError::Io { path, source }
+    };
+    fs::create_dir_all(dir).map_err(io_err(dir))?;
+    let file = BufReader::new(File::open(archive).map_err(io_err(archive))?);
+
+    match format {
+        ArchiveFormat::Zip => {
+            let zip_err = |source| RestoreError::Zip { path: archive.to_path_buf(), source };
+            ZipArchive::new(file).map_err(zip_err)?.extract(dir).map_err(zip_err)?;
+        }
+        ArchiveFormat::TarGz => tar::Archive::new(GzDecoder::new(file)).unpack(dir).map_err(io_err(archive))?,
+        ArchiveFormat::TarZst => {
+            let decoder = zstd::Decoder::with_buffer(file).map_err(io_err(archive))?;
+            tar::Archive::new(decoder).unpack(dir).map_err(io_err(archive))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backup::{create_archive, Upload},
+        target::{BackupTarget, LocalTarget},
+    };
+
+    /// A server folder, backup folder and local target inside a temp folder, removed when dropped
+    struct Fixture {
+        root: PathBuf,
+        config: Config,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            let root = std::env::temp_dir().join(format!("bouncer_restore_test_{}_{}", std::process::id(), name));
+            let _ = fs::remove_dir_all(&root);
+            let folder = |name: &str| root.join(name).to_string_lossy().to_string();
+            let config = Config {
+                server_folder: folder("server"),
+                backup_folder: folder("backups"),
+                backup_file_name: "server backup".to_string(),
+                backup_targets: vec![BackupTarget::Local(LocalTarget { path: folder("mirror") })],
+                ..Config::default()
+            };
+            write(&root.join("server"), "now");
+            fs::create_dir_all(root.join("backups")).unwrap();
+            fs::create_dir_all(root.join("mirror")).unwrap();
+            Self { root, config }
+        }
+
+        /// Backs up a server folder holding `level` from `created`, uploading it to the target when `upload` is set
+        fn backup(&self, created: &str, level: &str, upload: bool) -> String {
+            let created = NaiveDateTime::parse_from_str(created, "%Y-%m-%d %H:%M:%S").unwrap();
+            let snapshot = self.root.join("snapshot");
+            write(&snapshot, level);
+            let path = Path::new(&self.config.backup_folder).join(retention::archive_name(&self.config.backup_file_name, created, ArchiveFormat::Zip));
+            let mut archive = create_archive(&snapshot, &path, ArchiveFormat::Zip, None).unwrap();
+            fs::remove_dir_all(&snapshot).unwrap();
+            if upload {
+                let target = &self.config.backup_targets[0];
+                archive.uploads.push(Upload { target: target.to_string(), result: target.upload(&path) });
+                record(&self.config, &archive);
+            }
+            created.format(TIMESTAMP_FORMAT).to_string()
+        }
+
+        fn server(&self) -> PathBuf {
+            PathBuf::from(&self.config.server_folder)
+        }
+
+        /// every folder next to the server folder left by a restore
+        fn safety_copies(&self) -> Vec<PathBuf> {
+            let mut copies = fs::read_dir(&self.root)
+                .unwrap()
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.file_name().unwrap().to_string_lossy().starts_with("server before restore "))
+                .collect::<Vec<_>>();
+            copies.sort();
+            copies
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    /// makes a server folder at `dir` whose world's `level.dat` holds `level`
+    fn write(dir: &Path, level: &str) {
+        fs::create_dir_all(dir.join("world/empty")).unwrap();
+        fs::write(dir.join("world/level.dat"), level).unwrap();
+    }
+
+    fn level(dir: &Path) -> String {
+        fs::read_to_string(dir.join("world/level.dat")).unwrap()
+    }
+
+    #[test]
+    fn restores_a_local_backup_and_keeps_the_old_folder() {
+        let fixture = Fixture::new("local");
+        let id = fixture.backup("2024-05-01 00:55:00", "then", false);
+
+        let restored = restore(&fixture.config, &id).unwrap();
+        assert_eq!(restored.backup.id, "2024-05-01_00-55-00");
+        assert_eq!(level(&fixture.server()), "then");
+        assert!(fixture.server().join("world/empty").is_dir());
+        assert_eq!(fixture.safety_copies(), vec![restored.safety_copy.clone()]);
+        assert_eq!(level(&restored.safety_copy), "now");
+    }
+
+    #[test]
+    fn a_corrupt_archive_puts_the_old_folder_back() {
+        let fixture = Fixture::new("corrupt");
+        let id = fixture.backup("2024-05-01 00:55:00", "then", false);
+        let archive = find(&fixture.config, &id).unwrap().local.unwrap();
+        fs::write(&archive, "not a zip").unwrap();
+
+        assert!(matches!(restore(&fixture.config, &id), Err(RestoreError::Zip { .. })));
+        assert_eq!(level(&fixture.server()), "now");
+        assert!(fixture.safety_copies().is_empty());
+        assert!(matches!(restore(&fixture.config, "2024-05-02_00-55-00"), Err(RestoreError::UnknownBackup(_))));
+    }
+
+    #[test]
+    fn pruned_backups_are_listed_from_the_index_and_downloaded() {
+        let fixture = Fixture::new("pruned");
+        let pruned = fixture.backup("2024-05-01 00:55:00", "pruned", true);
+        let uploaded = fixture.backup("2024-05-02 00:55:00", "uploaded", true);
+        let local = fixture.backup("2024-05-03 00:55:00", "local", false);
+        let pruned_path = find(&fixture.config, &pruned).unwrap().local.unwrap();
+        fs::remove_file(&pruned_path).unwrap();
+
+        let target = fixture.config.backup_targets[0].to_string();
+        let listed = list(&fixture.config)
+            .into_iter()
+            .map(|backup| (backup.id, backup.local.is_some(), backup.targets, backup.archive_bytes > 0))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            listed,
+            [(local, true, vec![], true), (uploaded, true, vec![target.clone()], true), (pruned.clone(), false, vec![target], true)]
+        );
+
+        restore(&fixture.config, &pruned).unwrap();
+        assert_eq!(level(&fixture.server()), "pruned");
+        assert!(pruned_path.is_file());
+    }
+}
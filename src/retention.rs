@@ -0,0 +1,239 @@
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use chrono::{Datelike, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::backup::ArchiveFormat;
+
+/// the timestamp added to every archive name
+pub const TIMESTAMP_FORMAT: &str = "%Y-%m-%d_%H-%M-%S";
+
+/// Grandfather-father-son retention for the local backup folder.
+///
+/// A backup is kept if any rule wants it. The daily, weekly and monthly rules keep the newest backup of
+/// each of the most recent days, weeks and months that have a backup, so gaps where the server was down don't eat into them
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Retention {
+    /// always keep this many of the newest backups
+    pub keep_last: usize,
+    /// days to keep one backup for
+    pub daily: usize,
+    /// weeks to keep one backup for
+    pub weekly: usize,
+    /// months to keep one backup for
+    pub monthly: usize,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Self {
+            keep_last: 5,
+            daily: 7,
+            weekly: 4,
+            monthly: 6,
+        }
+    }
+}
+
+/// A backup archive found in the backup folder
+#[derive(Debug, Clone)]
+pub struct BackupFile {
+    pub path: PathBuf,
+    pub created: NaiveDateTime,
+    pub format: ArchiveFormat,
+}
+
+/// the name of a new archive made at `created`
+pub fn archive_name(file_name: &str, created: NaiveDateTime, format: ArchiveFormat) -> String {
+    format!("{} {}.{}", file_name, created.format(TIMESTAMP_FORMAT), format.extension())
+}
+
+/// the reverse of [`archive_name`], `None` if `name` isn't an archive made by the bouncer
+pub fn parse_archive_name(name: &str, file_name: &str) -> Option<(NaiveDateTime, ArchiveFormat)> {
+    let rest = name.strip_prefix(file_name)?.strip_prefix(' ')?;
+    let (timestamp, format) = [ArchiveFormat::Zip, ArchiveFormat::TarGz, ArchiveFormat::TarZst]
+        .into_iter()
+        .find_map(|format| Some((rest.strip_suffix(&format!(".{}", format.extension()))?, format)))?;
+    let created = NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT).ok()?;
+    Some((created, format))
+}
+
+/// Every archive in `folder` named by [`archive_name`], newest first.
+/// Anything else in the folder is left alone
+pub fn list_backups(folder: &Path, file_name: &str) -> io::Result<Vec<BackupFile>> {
+    let mut backups = Vec::new();
+    for entry in fs::read_dir(folder)?.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let Some((created, format)) = parse_archive_name(&name, file_name) else {
+            continue;
+        };
+        backups.push(BackupFile { path: entry.path(), created, format });
+    }
+    backups.sort_by_key(|backup| std::cmp::Reverse(backup.created));
+    Ok(backups)
+}
+
+/// works out which of `backups` (newest first) the policy keeps
+pub fn keep(backups: &[BackupFile], retention: &Retention) -> HashSet<PathBuf> {
+    let mut kept = HashSet::new();
+    kept.extend(backups.iter().take(retention.keep_last).map(|backup| backup.path.clone()));
+
+    let mut keep_per_period = |count: usize, period: &dyn Fn(&NaiveDateTime) -> (i32, u32)| {
+        let mut periods = HashSet::new();
+        for backup in backups {
+            if periods.len() == count {
+                break;
+            }
+            // newest first, so the first backup seen in a period is the one to keep
+            if periods.insert(period(&backup.created)) {
+                kept.insert(backup.path.clone());
+            }
+        }
+    };
+    keep_per_period(retention.daily, &|created| (created.year(), created.ordinal()));
+    keep_per_period(retention.weekly, &|created| {
+        let week = created.iso_week();
+        (week.year(), week.week())
+    });
+    keep_per_period(retention.monthly, &|created| (created.year(), created.month()));
+
+    kept
+}
+
+/// Deletes every backup in `folder` the retention policy doesn't keep
+pub fn prune(folder: &Path, file_name: &str, retention: &Retention) -> io::Result<()> {
+    let backups = list_backups(folder, file_name)?;
+    let kept = keep(&backups, retention);
+    for backup in backups.iter().filter(|backup| !kept.contains(&backup.path)) {
+        match fs::remove_file(&backup.path) {
+            Ok(_) => info!("Pruned old backup {}", backup.path.display()),
+            Err(io_err) => warn!("Failed to prune old backup {}: {}", backup.path.display(), io_err),
+        }
+    }
+    info!("Keeping {} of {} backups in {}", kept.len(), backups.len(), folder.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE_NAME: &str = "server backup";
+
+    /// backups made at each of `times`, which are given newest first like `list_backups` returns them
+    fn backups(times: &[&str]) -> Vec<BackupFile> {
+        times
+            .iter()
+            .map(|time| {
+                let created = NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M").unwrap();
+                BackupFile { path: PathBuf::from(archive_name(FILE_NAME, created, ArchiveFormat::Zip)), created, format: ArchiveFormat::Zip }
+            })
+            .collect()
+    }
+
+    /// the times of the backups that are kept, newest first
+    fn kept(backups: &[BackupFile], retention: Retention) -> Vec<String> {
+        let kept = keep(backups, &retention);
+        backups
+            .iter()
+            .filter(|backup| kept.contains(&backup.path))
+            .map(|backup| backup.created.format("%Y-%m-%d %H:%M").to_string())
+            .collect()
+    }
+
+    fn retention(keep_last: usize, daily: usize, weekly: usize, monthly: usize) -> Retention {
+        Retention { keep_last, daily, weekly, monthly }
+    }
+
+    #[test]
+    fn zero_counts_keep_nothing() {
+        let backups = backups(&["2024-06-03 00:55", "2024-06-02 00:55", "2024-05-01 00:55"]);
+        assert!(kept(&backups, retention(0, 0, 0, 0)).is_empty());
+    }
+
+    #[test]
+    fn keep_last_keeps_the_newest() {
+        let backups = backups(&["2024-06-03 12:00", "2024-06-03 00:55", "2024-06-02 00:55"]);
+        assert_eq!(kept(&backups, retention(2, 0, 0, 0)), ["2024-06-03 12:00", "2024-06-03 00:55"]);
+        assert_eq!(kept(&backups, retention(10, 0, 0, 0)).len(), 3);
+    }
+
+    #[test]
+    fn daily_keeps_the_newest_of_each_day_skipping_days_without_one() {
+        let backups = backups(&["2024-06-10 12:00", "2024-06-10 00:55", "2024-06-03 00:55", "2024-06-01 18:00", "2024-06-01 00:55"]);
+        assert_eq!(kept(&backups, retention(0, 3, 0, 0)), ["2024-06-10 12:00", "2024-06-03 00:55", "2024-06-01 18:00"]);
+    }
+
+    #[test]
+    fn weekly_goes_by_iso_week() {
+        // 2024-06-02 is a sunday, so it's in the week before the monday after it
+        let backups = backups(&["2024-06-03 00:55", "2024-06-02 00:55", "2024-05-27 00:55", "2024-05-26 00:55"]);
+        assert_eq!(kept(&backups, retention(0, 0, 2, 0)), ["2024-06-03 00:55", "2024-06-02 00:55"]);
+        assert_eq!(kept(&backups, retention(0, 0, 3, 0)), ["2024-06-03 00:55", "2024-06-02 00:55", "2024-05-26 00:55"]);
+    }
+
+    #[test]
+    fn weekly_goes_by_iso_week_across_years() {
+        // 2024-12-30 is in the first week of 2025, along with 2025-01-02
+        let backups = backups(&["2025-01-02 00:55", "2024-12-30 00:55", "2024-12-29 00:55"]);
+        assert_eq!(kept(&backups, retention(0, 0, 2, 0)), ["2025-01-02 00:55", "2024-12-29 00:55"]);
+    }
+
+    #[test]
+    fn monthly_keeps_the_newest_of_each_month() {
+        let backups = backups(&["2024-06-03 00:55", "2024-05-31 00:55", "2024-05-01 00:55", "2024-03-01 00:55", "2023-06-15 00:55"]);
+        assert_eq!(kept(&backups, retention(0, 0, 0, 3)), ["2024-06-03 00:55", "2024-05-31 00:55", "2024-03-01 00:55"]);
+    }
+
+    #[test]
+    fn rules_that_overlap_keep_what_any_of_them_wants() {
+        let backups = backups(&[
+            "2024-06-03 12:00",
+            "2024-06-03 00:55",
+            "2024-06-02 00:55",
+            "2024-05-31 00:55",
+            "2024-05-20 00:55",
+            "2024-04-10 00:55",
+            "2024-03-01 00:55",
+        ]);
+        // the newest is wanted by every rule, 2024-06-02 by daily and weekly, 2024-05-31 by monthly, 2024-05-20 by weekly and 2024-04-10 by monthly
+        assert_eq!(
+            kept(&backups, retention(1, 2, 3, 3)),
+            ["2024-06-03 12:00", "2024-06-02 00:55", "2024-05-31 00:55", "2024-05-20 00:55", "2024-04-10 00:55"]
+        );
+    }
+
+    #[test]
+    fn archive_names_round_trip() {
+        let created = NaiveDateTime::parse_from_str("2024-05-01 00:55:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        for format in [ArchiveFormat::Zip, ArchiveFormat::TarGz, ArchiveFormat::TarZst] {
+            let name = archive_name(FILE_NAME, created, format);
+            assert_eq!(parse_archive_name(&name, FILE_NAME), Some((created, format)));
+        }
+        assert_eq!(archive_name(FILE_NAME, created, ArchiveFormat::TarGz), "server backup 2024-05-01_00-55-00.tar.gz");
+    }
+
+    #[test]
+    fn archive_names_sharing_a_prefix_are_told_apart() {
+        let created = NaiveDateTime::parse_from_str("2024-05-01 00:55:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let other = archive_name("server backup 2", created, ArchiveFormat::Zip);
+        assert_eq!(parse_archive_name(&other, FILE_NAME), None);
+        assert_eq!(parse_archive_name(&other, "server backup 2"), Some((created, ArchiveFormat::Zip)));
+        assert_eq!(parse_archive_name(&archive_name(FILE_NAME, created, ArchiveFormat::Zip), "server backup 2"), None);
+    }
+
+    #[test]
+    fn other_files_are_not_archives() {
+        assert_eq!(parse_archive_name("server backup.zip", FILE_NAME), None);
+        assert_eq!(parse_archive_name("server backup 2024-05-01_00-55-00.rar", FILE_NAME), None);
+        assert_eq!(parse_archive_name("server backup 2024-05-01.zip", FILE_NAME), None);
+        assert_eq!(parse_archive_name("server backup2024-05-01_00-55-00.zip", FILE_NAME), None);
+        assert_eq!(parse_archive_name("notes.txt", FILE_NAME), None);
+    }
+}
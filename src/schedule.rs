@@ -0,0 +1,511 @@
+use std::{
+    fmt::{self, Display},
+    str::FromStr,
+    time::Duration,
+};
+
+use chrono::{DateTime, Days, LocalResult, NaiveDateTime, NaiveTime, TimeDelta, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::config::{BackupMode, Config, Problems, Warning};
+
+/// how long before an offline backup players are warned that the server is going down
+pub const OFFLINE_BACKUP_WARNING: Duration = Duration::from_secs(60);
+
+/// When the server is restarted and backed up, and anything else that should happen at set times
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct ScheduleConfig {
+    /// the IANA timezone the times are in like `Europe/London`, left out for the system's timezone
+    pub timezone: Option<String>,
+    /// when the server restarts, left out to restart `restart_duration` seconds after it starts
+    pub restart: Option<When>,
+    /// when the automatic backup runs, left out to back up every day at `backup_time`
+    pub backup: Option<When>,
+    /// console commands run at set times
+    pub commands: Vec<ScheduledCommand>,
+    /// messages said to the players at set times
+    pub broadcasts: Vec<ScheduledBroadcast>,
+}
+
+/// When something happens, either a cron expression or a list of times of day
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum When {
+    /// `"0 4 * * *"` like crontab, or with seconds as the first field
+    Cron(String),
+    /// `["04:00", "16:00"]`, every day
+    Times(Vec<String>),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ScheduledCommand {
+    pub at: When,
+    /// sent to the server console as if it was typed after `cmd`
+    pub command: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ScheduledBroadcast {
+    pub at: When,
+    pub msg: String,
+}
+
+impl ScheduleConfig {
+    /// adds anything wrong with the schedule to `problems`, `field` being where it is in the config
+    pub fn validate(&self, field: &str, problems: &mut Problems) {
+        if let Err(msg) = self.zone() {
+            problems.add(format!("{}.timezone", field), msg);
+        }
+        let mut check = |when: &When, at: String| {
+            if let Err(msg) = when.parse() {
+                problems.add(at, msg);
+            }
+        };
+        if let Some(restart) = &self.restart {
+            check(restart, format!("{}.restart", field));
+        }
+        if let Some(backup) = &self.backup {
+            check(backup, format!("{}.backup", field));
+        }
+        for (i, command) in self.commands.iter().enumerate() {
+            check(&command.at, format!("{}.commands[{}].at", field, i));
+        }
+        for (i, broadcast) in self.broadcasts.iter().enumerate() {
+            check(&broadcast.at, format!("{}.broadcasts[{}].at", field, i));
+        }
+
+        for (i, command) in self.commands.iter().enumerate() {
+            if command.command.trim().is_empty() {
+                problems.add(format!("{}.commands[{}].command", field, i), "can't be empty");
+            }
+        }
+        for (i, broadcast) in self.broadcasts.iter().enumerate() {
+            if broadcast.msg.trim().is_empty() {
+                problems.add(format!("{}.broadcasts[{}].msg", field, i), "can't be empty");
+            }
+        }
+    }
+
+    fn zone(&self) -> Result<Zone, String> {
+        match &self.timezone {
+            None => Ok(Zone::Local),
+            Some(name) => Tz::from_str(name)
+                .map(Zone::Named)
+                .map_err(|_| format!("{:?} isn't a timezone name like \"America/New_York\" or \"UTC\"", name)),
+        }
+    }
+}
+
+impl When {
+    fn parse(&self) -> Result<Recurrence, String> {
+        match self {
+            When::Cron(expression) => {
+                // the cron crate wants seconds first, so add them to crontab style expressions
+                let full = match expression.split_whitespace().count() {
+                    5 => format!("0 {}", expression),
+                    _ => expression.clone(),
+                };
+                cron::Schedule::from_str(&full)
+                    .map(|schedule| Recurrence::Cron(Box::new(schedule)))
+                    // the error repeats the expression with a marker under it before saying what is wrong
+                    .map_err(|err| format!("{:?} isn't a valid cron expression: {}", expression, err.to_string().lines().last().unwrap_or_default()))
+            }
+            When::Times(times) if times.is_empty() => Err("needs at least one time".to_string()),
+            When::Times(times) => {
+                let mut parsed = times
+                    .iter()
+                    .map(|time| NaiveTime::parse_from_str(time, "%H:%M").map_err(|_| format!("{:?} isn't a 24 hour time like \"00:55\"", time)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                parsed.sort();
+                parsed.dedup();
+                Ok(Recurrence::Times(parsed))
+            }
+        }
+    }
+}
+
+/// A parsed `When`
+enum Recurrence {
+    Cron(Box<cron::Schedule>),
+    Times(Vec<NaiveTime>),
+}
+
+impl Recurrence {
+    /// every wall clock time after `after` this happens at, in order
+    fn wall_times(&self, after: NaiveDateTime) -> Box<dyn Iterator<Item = NaiveDateTime> + '_> {
+        match self {
+            // cron is run on the wall clock, treating it as utc so it never sees a DST change
+            Recurrence::Cron(schedule) => Box::new(schedule.after(&Utc.from_utc_datetime(&after)).map(|time| time.naive_utc())),
+            Recurrence::Times(times) => Box::new(
+                (0..)
+                    .map_while(move |days| after.date().checked_add_days(Days::new(days)))
+                    .flat_map(move |date| times.iter().map(move |time| date.and_time(*time)))
+                    .filter(move |time| *time > after),
+            ),
+        }
+    }
+}
+
+/// The timezone the schedule's wall clock times are in
+#[derive(Clone, Copy)]
+pub enum Zone {
+    Local,
+    Named(Tz),
+}
+
+impl Zone {
+    fn wall_time(&self, time: DateTime<Utc>) -> NaiveDateTime {
+        match self {
+            Zone::Local => time.with_timezone(&chrono::Local).naive_local(),
+            Zone::Named(tz) => time.with_timezone(tz).naive_local(),
+        }
+    }
+
+    /// The moment a wall clock time happens. A time that happens twice when the clocks go back is the first one,
+    /// and a time that is skipped when the clocks go forward happens as soon as they have
+    fn resolve(&self, wall_time: NaiveDateTime) -> DateTime<Utc> {
+        fn resolve_in<Z: TimeZone>(zone: &Z, wall_time: NaiveDateTime) -> DateTime<Utc> {
+            let mut time = wall_time;
+            loop {
+                match zone.from_local_datetime(&time) {
+                    LocalResult::Single(resolved) | LocalResult::Ambiguous(resolved, _) => return resolved.with_timezone(&Utc),
+                    // the clocks skip over this time, they never skip more than a day
+                    LocalResult::None if time - wall_time < TimeDelta::days(1) => {
+                        time = time.with_second(0).unwrap_or(time) + TimeDelta::minutes(1);
+                    }
+                    LocalResult::None => return Utc.from_utc_datetime(&wall_time),
+                }
+            }
+        }
+        match self {
+            Zone::Local => resolve_in(&chrono::Local, wall_time),
+            Zone::Named(tz) => resolve_in(tz, wall_time),
+        }
+    }
+
+    /// formats `time` on the wall clock with the timezone's abbreviation
+    pub fn format(&self, time: DateTime<Utc>) -> String {
+        const FORMAT: &str = "%Y-%m-%d %H:%M:%S %Z";
+        match self {
+            Zone::Local => time.with_timezone(&chrono::Local).format(FORMAT).to_string(),
+            Zone::Named(tz) => time.with_timezone(tz).format(FORMAT).to_string(),
+        }
+    }
+}
+
+/// Something that happens on a schedule other than the restart
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    Backup,
+    /// a console command
+    Command(String),
+    /// a message said to the players
+    Broadcast(String),
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Backup => write!(f, "back up the server"),
+            Action::Command(cmd) => write!(f, "run `{}`", cmd),
+            Action::Broadcast(msg) => write!(f, "say {:?}", msg),
+        }
+    }
+}
+
+struct Job {
+    action: Action,
+    recurrence: Recurrence,
+    /// how long before its time the action is started
+    lead: Duration,
+    /// when the action is next started
+    next: Option<DateTime<Utc>>,
+}
+
+enum Restart {
+    /// `restart_duration` after the server starts
+    Every(Duration),
+    At(Recurrence),
+}
+
+/// Works out when the scheduled restarts, backups, commands and broadcasts happen
+pub struct Scheduler {
+    zone: Zone,
+    restart: Restart,
+    jobs: Vec<Job>,
+}
+
+impl Scheduler {
+    /// The config must have been validated
+    pub fn new(config: &Config, now: DateTime<Utc>) -> Self {
+        let schedule = &config.schedule;
+        let zone = schedule.zone().expect("the timezone is checked when the config is loaded");
+        let parse = |when: &When| when.parse().expect("the schedule is checked when the config is loaded");
+
+        let restart = match &schedule.restart {
+            Some(when) => Restart::At(parse(when)),
+            None => Restart::Every(Duration::from_secs(config.restart_duration)),
+        };
+        let backup = match &schedule.backup {
+            Some(when) => parse(when),
+            None => Recurrence::Times(vec![config.backup_time().expect("backup_time is checked when the config is loaded")]),
+        };
+
+        let mut jobs = vec![Job {
+            action: Action::Backup,
+            recurrence: backup,
+            // players get a minute's warning before an offline backup shuts the server down
+            lead: match config.backup_mode {
+                BackupMode::Offline => OFFLINE_BACKUP_WARNING,
+                BackupMode::Online => Duration::ZERO,
+            },
+            next: None,
+        }];
+        for command in &schedule.commands {
+            jobs.push(Job { action: Action::Command(command.command.clone()), recurrence: parse(&command.at), lead: Duration::ZERO, next: None });
+        }
+        for broadcast in &schedule.broadcasts {
+            jobs.push(Job { action: Action::Broadcast(broadcast.msg.clone()), recurrence: parse(&broadcast.at), lead: Duration::ZERO, next: None });
+        }
+
+        let mut scheduler = Self { zone, restart, jobs };
+        for i in 0..scheduler.jobs.len() {
+            scheduler.jobs[i].next = scheduler.next_start(&scheduler.jobs[i], now);
+        }
+        scheduler
+    }
+
+    /// the first time `recurrence` happens after `after`
+    fn next_time(&self, recurrence: &Recurrence, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        recurrence
+            .wall_times(self.zone.wall_time(after))
+            // a wall clock time in the hour the clocks go back twice can resolve to before `after`
+            .take(10_000)
+            .map(|wall_time| self.zone.resolve(wall_time))
+            .find(|time| *time > after)
+    }
+
+    fn next_start(&self, job: &Job, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let lead = TimeDelta::from_std(job.lead).unwrap_or_default();
+        self.next_time(&job.recurrence, after + lead).map(|time| time - lead)
+    }
+
+    /// When the server restarts if its countdown started at `started`. `None` if the restart schedule never happens again
+    pub fn next_restart(&self, started: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match &self.restart {
+            Restart::Every(duration) => TimeDelta::from_std(*duration).ok().and_then(|duration| started.checked_add_signed(duration)),
+            Restart::At(recurrence) => self.next_time(recurrence, started),
+        }
+    }
+
+    /// The actions that are due by `now`. An action that was missed more than once, like when the computer was asleep,
+    /// only happens once
+    pub fn due(&mut self, now: DateTime<Utc>) -> Vec<Action> {
+        let mut due = Vec::new();
+        for i in 0..self.jobs.len() {
+            if self.jobs[i].next.is_some_and(|next| next <= now) {
+                due.push(self.jobs[i].action.clone());
+                self.jobs[i].next = self.next_start(&self.jobs[i], now);
+                if let Some(next) = self.jobs[i].next {
+                    info!("Next scheduled {} at {}", self.jobs[i].action, self.zone.format(next));
+                }
+            }
+        }
+        due
+    }
+
+    /// the next time an action is due
+    pub fn next_deadline(&self) -> Option<DateTime<Utc>> {
+        self.jobs.iter().filter_map(|job| job.next).min()
+    }
+
+    /// every action with the time it next happens at, not counting any warning before it
+    pub fn upcoming(&self) -> impl Iterator<Item = (&Action, Option<DateTime<Utc>>)> {
+        self.jobs.iter().map(|job| {
+            let lead = TimeDelta::from_std(job.lead).unwrap_or_default();
+            (&job.action, job.next.map(|next| next + lead))
+        })
+    }
+
+    pub fn zone(&self) -> Zone {
+        self.zone
+    }
+}
+
+/// What happens when a countdown runs out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Shutdown {
+    /// the restart from `restart_duration` or `schedule.restart`
+    ScheduledRestart,
+    /// `restart`
+    ManualRestart,
+    /// an offline backup
+    Backup,
+    /// `restore <id>`
+    Restore(String),
+    /// `stop`
+    Exit,
+}
+
+impl Display for Shutdown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Shutdown::ScheduledRestart => write!(f, "scheduled restart"),
+            Shutdown::ManualRestart => write!(f, "restart"),
+            Shutdown::Backup => write!(f, "backup"),
+            Shutdown::Restore(_) => write!(f, "restore"),
+            Shutdown::Exit => write!(f, "shutdown"),
+        }
+    }
+}
+
+/// The countdown to the server going down, along with the warnings said on the way
+pub struct Countdown {
+    pub shutdown: Shutdown,
+    /// `None` if the restart schedule never happens again
+    pub at: Option<DateTime<Utc>>,
+    warnings: Vec<Warning>,
+    /// how many of the warnings have been said or skipped
+    said: usize,
+    /// the time was changed with `cancel` or `postpone`, so a reload keeps it
+    pub moved: bool,
+}
+
+impl Countdown {
+    /// Warnings that were due before `now` are skipped. The bouncer stopping isn't a restart so it gets no restart warnings
+    pub fn new(shutdown: Shutdown, at: Option<DateTime<Utc>>, warnings: Vec<Warning>, now: DateTime<Utc>) -> Self {
+        let mut countdown = Self { shutdown, at: None, warnings: Vec::new(), said: 0, moved: false };
+        countdown.plan(at, warnings, now);
+        countdown
+    }
+
+    /// Runs the countdown out at `at` with `warnings` instead, like after a reload. Warnings that were due before `now` are skipped
+    pub fn plan(&mut self, at: Option<DateTime<Utc>>, mut warnings: Vec<Warning>, now: DateTime<Utc>) {
+        if self.shutdown == Shutdown::Exit {
+            warnings.clear();
+        }
+        self.at = at;
+        self.warnings = warnings;
+        self.said = self.due(now);
+    }
+
+    /// Moves the countdown to `at` by hand and starts the warnings over from there
+    pub fn move_to(&mut self, at: Option<DateTime<Utc>>, now: DateTime<Utc>) {
+        self.at = at;
+        self.said = self.due(now);
+        self.moved = true;
+    }
+
+    fn warning_time(&self, warning: &Warning) -> Option<DateTime<Utc>> {
+        Some(self.at? - TimeDelta::seconds(warning.before as i64))
+    }
+
+    /// how many of the warnings are due by `now`
+    fn due(&self, now: DateTime<Utc>) -> usize {
+        self.warnings
+            .iter()
+            .take_while(|warning| self.warning_time(warning).is_some_and(|time| time <= now))
+            .count()
+    }
+
+    /// The warning to say now if there is one. Only the latest is said if several are due at once
+    pub fn warning(&mut self, now: DateTime<Utc>) -> Option<&Warning> {
+        let due = self.due(now);
+        if due <= self.said {
+            return None;
+        }
+        self.said = due;
+        self.warnings.get(due - 1)
+    }
+
+    pub fn finished(&self, now: DateTime<Utc>) -> bool {
+        self.at.is_some_and(|at| at <= now)
+    }
+
+    /// when the next warning is due or the countdown runs out
+    pub fn next_deadline(&self) -> Option<DateTime<Utc>> {
+        match self.warnings.get(self.said) {
+            Some(warning) => self.warning_time(warning),
+            None => self.at,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(time: &str) -> DateTime<Utc> {
+        time.parse().unwrap()
+    }
+
+    fn wall(time: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    fn new_york() -> Zone {
+        Zone::Named(chrono_tz::America::New_York)
+    }
+
+    /// a scheduler that restarts at `restart` in New York, where the clocks went forward at 2024-03-10 02:00 and back at 2024-11-03 02:00
+    fn scheduler(restart: When) -> Scheduler {
+        let mut config = Config::default();
+        config.schedule.timezone = Some("America/New_York".to_string());
+        config.schedule.restart = Some(restart);
+        Scheduler::new(&config, utc("2024-01-01T00:00:00Z"))
+    }
+
+    #[test]
+    fn resolve_moves_a_skipped_time_to_when_the_clocks_have_gone_forward() {
+        // 02:30 never happens, 02:00 EST is followed by 03:00 EDT
+        assert_eq!(new_york().resolve(wall("2024-03-10 02:30")), utc("2024-03-10T07:00:00Z"));
+        assert_eq!(new_york().resolve(wall("2024-03-10 01:59")), utc("2024-03-10T06:59:00Z"));
+        assert_eq!(new_york().resolve(wall("2024-03-10 03:00")), utc("2024-03-10T07:00:00Z"));
+    }
+
+    #[test]
+    fn resolve_takes_the_first_of_an_ambiguous_time() {
+        // 01:30 happens in EDT then again in EST
+        assert_eq!(new_york().resolve(wall("2024-11-03 01:30")), utc("2024-11-03T05:30:00Z"));
+        assert_eq!(new_york().resolve(wall("2024-11-03 02:00")), utc("2024-11-03T07:00:00Z"));
+    }
+
+    #[test]
+    fn next_restart_across_the_clocks_going_forward() {
+        let scheduler = scheduler(When::Times(vec!["02:30".to_string()]));
+        let first = scheduler.next_restart(utc("2024-03-09T12:00:00Z")).unwrap();
+        assert_eq!(first, utc("2024-03-10T07:00:00Z"));
+        // back to 02:30 the day after, now in EDT
+        assert_eq!(scheduler.next_restart(first).unwrap(), utc("2024-03-11T06:30:00Z"));
+    }
+
+    #[test]
+    fn next_restart_across_the_clocks_going_back() {
+        let scheduler = scheduler(When::Times(vec!["01:30".to_string()]));
+        let first = scheduler.next_restart(utc("2024-11-03T00:00:00Z")).unwrap();
+        assert_eq!(first, utc("2024-11-03T05:30:00Z"));
+        // the second 01:30 that night is skipped, whether counting from the first one or from the hour in between
+        assert_eq!(scheduler.next_restart(first).unwrap(), utc("2024-11-04T06:30:00Z"));
+        assert_eq!(scheduler.next_restart(utc("2024-11-03T06:00:00Z")).unwrap(), utc("2024-11-04T06:30:00Z"));
+    }
+
+    #[test]
+    fn next_restart_with_cron_across_both_transitions() {
+        let scheduler = scheduler(When::Cron("30 2 * * *".to_string()));
+        assert_eq!(scheduler.next_restart(utc("2024-03-09T12:00:00Z")).unwrap(), utc("2024-03-10T07:00:00Z"));
+        assert_eq!(scheduler.next_restart(utc("2024-03-10T07:00:00Z")).unwrap(), utc("2024-03-11T06:30:00Z"));
+        assert_eq!(scheduler.next_restart(utc("2024-11-02T12:00:00Z")).unwrap(), utc("2024-11-03T07:30:00Z"));
+    }
+
+    #[test]
+    fn next_restart_every_duration_ignores_the_clocks() {
+        let mut config = Config::default();
+        config.schedule.timezone = Some("America/New_York".to_string());
+        config.restart_duration = 3600;
+        let scheduler = Scheduler::new(&config, utc("2024-01-01T00:00:00Z"));
+        assert_eq!(scheduler.next_restart(utc("2024-03-10T06:30:00Z")).unwrap(), utc("2024-03-10T07:30:00Z"));
+    }
+}
@@ -0,0 +1,28 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// set once ctrl-c or SIGTERM arrives
+static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Catches ctrl-c and SIGTERM so the server gets stopped properly. It runs in its own process group,
+/// so it doesn't get them itself and would be left running without the bouncer
+#[cfg(unix)]
+pub fn install() {
+    extern "C" fn handle(_signal: libc::c_int) {
+        STOP_REQUESTED.store(true, Ordering::SeqCst);
+    }
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        // SAFETY: the handler only stores to an atomic, which is safe to do in a signal handler
+        unsafe {
+            libc::signal(signal, handle as extern "C" fn(libc::c_int) as libc::sighandler_t);
+        }
+    }
+}
+
+/// ctrl-c reaches the whole console on windows, server included
+#[cfg(not(unix))]
+pub fn install() {}
+
+/// whether the bouncer was asked to exit by a signal
+pub fn stop_requested() -> bool {
+    STOP_REQUESTED.load(Ordering::SeqCst)
+}
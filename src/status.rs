@@ -0,0 +1,189 @@
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::{backup::{format_bytes, Archive, BackupError}, config::format_remaining, rcon, schedule::{Shutdown, Zone}};
+
+/// What the bouncer and the server are up to, printed by `status`
+#[derive(Serialize)]
+pub struct Status {
+    pub state: State,
+    /// the pid of the server, `None` while it's down
+    pub pid: Option<u32>,
+    pub server_started: Option<DateTime<Utc>>,
+    pub uptime_secs: Option<u64>,
+    /// what the running countdown ends in, usually the scheduled restart
+    pub countdown: Option<Planned>,
+    pub next_backup: Option<Planned>,
+    pub backup_running: bool,
+    pub last_backup: Option<BackupRecord>,
+    /// crashes since the bouncer started
+    pub crashes: u32,
+    pub players: Vec<String>,
+    /// resident memory of the server and everything it started, like the JVM under a start script
+    pub memory_bytes: Option<u64>,
+}
+
+/// What the bouncer is in the middle of
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum State {
+    /// counting down to the scheduled restart
+    Normal,
+    /// counting down to a restart asked for with `restart`
+    Restarting,
+    /// counting down to `stop`
+    ShuttingDown,
+    /// counting down to an offline backup, or a backup is running
+    BackingUp,
+    /// counting down to `restore <id>`
+    Restoring,
+}
+
+impl State {
+    pub fn new(countdown: &Shutdown, backup_running: bool) -> Self {
+        match countdown {
+            _ if backup_running => State::BackingUp,
+            Shutdown::ScheduledRestart => State::Normal,
+            Shutdown::ManualRestart => State::Restarting,
+            Shutdown::Exit => State::ShuttingDown,
+            Shutdown::Backup => State::BackingUp,
+            Shutdown::Restore(_) => State::Restoring,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Planned {
+    pub what: String,
+    pub at: DateTime<Utc>,
+    pub in_secs: u64,
+}
+
+impl Planned {
+    pub fn new(what: String, at: DateTime<Utc>, now: DateTime<Utc>) -> Self {
+        Self { what, at, in_secs: (at - now).num_seconds().max(0) as u64 }
+    }
+}
+
+/// How the last backup went
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupRecord {
+    pub finished: DateTime<Utc>,
+    /// the archive was made and uploaded to every target
+    pub ok: bool,
+    /// the archive and its size, or what went wrong
+    pub detail: String,
+}
+
+impl BackupRecord {
+    pub fn new(result: &Result<Archive, BackupError>) -> Self {
+        let (ok, detail) = match result {
+            Ok(archive) => {
+                let failed = archive.uploads.iter().filter(|upload| upload.result.is_err()).map(|upload| upload.target.as_str()).collect::<Vec<_>>();
+                let made = format!("{} ({})", archive.path.display(), format_bytes(archive.archive_bytes));
+                match failed.is_empty() {
+                    true => (true, made),
+                    false => (false, format!("{} but the upload to {} failed", made, failed.join(", "))),
+                }
+            },
+            Err(err) => (false, err.to_string()),
+        };
+        Self { finished: Utc::now(), ok, detail }
+    }
+}
+
+impl Status {
+    /// The status written out for someone reading the console
+    pub fn lines(&self, zone: Zone) -> Vec<String> {
+        let mut lines = Vec::new();
+        lines.push(match (self.pid, self.uptime_secs) {
+            (Some(pid), Some(uptime)) => format!("Server running with pid {} for {}", pid, format_remaining(uptime)),
+            _ => "Server is down".to_string(),
+        });
+        lines.push(format!("App state {:?}, {} crashes since the bouncer started", self.state, self.crashes));
+        lines.push(match &self.countdown {
+            Some(planned) => format!("Next {} at {} in {}", planned.what, zone.format(planned.at), format_remaining(planned.in_secs)),
+            None => "No restart is coming up".to_string(),
+        });
+        lines.push(match (&self.next_backup, self.backup_running) {
+            (_, true) => "A backup is running now".to_string(),
+            (Some(planned), false) => format!("Next backup at {} in {}", zone.format(planned.at), format_remaining(planned.in_secs)),
+            (None, false) => "No backup is scheduled".to_string(),
+        });
+        lines.push(match &self.last_backup {
+            Some(record) => format!("Last backup {} at {}: {}", if record.ok {"succeeded"} else {"FAILED"}, zone.format(record.finished), record.detail),
+            None => "No backup has been made yet".to_string(),
+        });
+        lines.push(match self.players.is_empty() {
+            true => "0 players online".to_string(),
+            false => format!("{} players online: {}", self.players.len(), self.players.join(", ")),
+        });
+        if let Some(bytes) = self.memory_bytes {
+            lines.push(format!("Server memory {}", format_bytes(bytes)));
+        }
+        lines
+    }
+}
+
+/// Adds up the resident memory of the process and all of its children
+#[cfg(target_os = "linux")]
+pub fn memory(pid: u32) -> Option<u64> {
+    let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    let kib = status.lines().find_map(|line| line.strip_prefix("VmRSS:"))?.trim().trim_end_matches("kB").trim().parse::<u64>().ok()?;
+    Some(kib * 1024 + children(pid).into_iter().filter_map(memory).sum::<u64>())
+}
+
+/// Adds up the CPU time the process and all of its children have used, in seconds
+#[cfg(target_os = "linux")]
+pub fn cpu_seconds(pid: u32) -> Option<f64> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // the name in brackets can hold spaces, so count the fields after it. utime and stime are the 14th and 15th
+    let mut fields = stat.rsplit_once(')')?.1.split_whitespace().skip(11);
+    let ticks = fields.next()?.parse::<u64>().ok()? + fields.next()?.parse::<u64>().ok()?;
+    // SAFETY: sysconf only reads a system setting
+    let ticks_per_second = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    if ticks_per_second <= 0 {
+        return None;
+    }
+    Some(ticks as f64 / ticks_per_second as f64 + children(pid).into_iter().filter_map(cpu_seconds).sum::<f64>())
+}
+
+#[cfg(target_os = "linux")]
+fn children(pid: u32) -> Vec<u32> {
+    let children = std::fs::read_to_string(format!("/proc/{}/task/{}/children", pid, pid)).unwrap_or_default();
+    children.split_whitespace().filter_map(|child| child.parse().ok()).collect()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn memory(_pid: u32) -> Option<u64> {
+    None
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn cpu_seconds(_pid: u32) -> Option<f64> {
+    None
+}
+
+/// Whether a server is running in the folder. It holds a lock on the `session.lock` in its world for as long as it runs
+#[cfg(unix)]
+pub fn world_locked(server_folder: &Path) -> bool {
+    use std::os::fd::AsRawFd;
+
+    let properties = std::fs::read_to_string(server_folder.join("server.properties")).unwrap_or_default();
+    let level = rcon::parse_properties(&properties).remove("level-name").filter(|level| !level.is_empty()).unwrap_or_else(|| "world".to_string());
+    let Ok(file) = std::fs::File::open(server_folder.join(level).join("session.lock")) else {
+        return false;
+    };
+    // SAFETY: flock is plain old data, and F_GETLK only fills it in with whoever holds a conflicting lock
+    let mut lock: libc::flock = unsafe { std::mem::zeroed() };
+    lock.l_type = libc::F_WRLCK as libc::c_short;
+    lock.l_whence = libc::SEEK_SET as libc::c_short;
+    let result = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETLK, &mut lock) };
+    result == 0 && lock.l_type != libc::F_UNLCK as libc::c_short
+}
+
+#[cfg(not(unix))]
+pub fn world_locked(_server_folder: &Path) -> bool {
+    false
+}